@@ -0,0 +1,401 @@
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+mod iso;
+
+use super::types::{MachineData, MachinegenError, System};
+use super::workspace::Workspace;
+use super::{log, pull, render, util, validate};
+
+/// Plan saved by `build --terraform` and applied by `deploy`.
+pub const PLAN_FILE: &str = "machinegen.tfplan";
+
+/// Files read by cloud-init from a NoCloud seed, the first two are mandatory.
+const SEED_FILES: [&str; 3] = ["user-data", "meta-data", "network-config"];
+
+pub fn run(sub_match: &clap::ArgMatches) -> Result<(), MachinegenError> {
+    let force = sub_match.contains_id("force");
+    let (cloud, terraform) = if sub_match.contains_id("cloud") {
+        (true, false)
+    } else if sub_match.contains_id("terraform") {
+        (false, true)
+    } else if sub_match.contains_id("all") {
+        (true, true)
+    } else {
+        log::warning("Please specify what to build. Use --help for more information.");
+        return Ok(());
+    };
+
+    let data = util::process_relations()?;
+
+    // Nothing gets built out of a user config that doesn't fit the machine config
+    let config = validate::check(&data)?;
+
+    if cloud {
+        cloud_init(&data, &config, force)?;
+    }
+    if terraform {
+        terraform_project(
+            &data,
+            &config,
+            sub_match.get_one::<String>("image").map(String::as_str),
+            force,
+        )?;
+    }
+    Ok(())
+}
+
+/// Folder where the rendered templates of a system are written.
+pub fn output_path(workspace: &Workspace, system: &System) -> PathBuf {
+    match system {
+        System::Guest => workspace.build().join("cloud-init"),
+        System::Host => workspace.build().join("terraform"),
+    }
+}
+
+pub fn seed_path(workspace: &Workspace) -> PathBuf {
+    workspace.build().join("seed.iso")
+}
+
+pub fn seed_lock_path(workspace: &Workspace) -> PathBuf {
+    workspace.build().join("seed.lock")
+}
+
+pub fn terraform_path(workspace: &Workspace) -> PathBuf {
+    output_path(workspace, &System::Host)
+}
+
+pub fn plan_path(workspace: &Workspace) -> PathBuf {
+    terraform_path(workspace).join(PLAN_FILE)
+}
+
+pub fn plan_lock_path(workspace: &Workspace) -> PathBuf {
+    workspace.build().join("plan.lock")
+}
+
+/// Hash of everything a plan was made from: the Terraform project files, the seed image
+/// inputs and the base image checksum. A plan is stale once this changes.
+pub fn plan_inputs(base_image: &Path) -> Result<String, MachinegenError> {
+    let project = terraform_path(util::workspace());
+    let mut hasher = Sha256::new();
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    collect_project_files(&project, &mut files)
+        .map_err(|error| MachinegenError::io(&project, error))?;
+    files.sort();
+    for file in files {
+        let content = fs::read(&file).map_err(|error| MachinegenError::io(&file, error))?;
+        hasher.update(
+            file.strip_prefix(&project)
+                .unwrap_or(&file)
+                .to_string_lossy()
+                .as_bytes(),
+        );
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(content);
+    }
+
+    match util::read_lock(&seed_lock_path(util::workspace())).get("inputs") {
+        Some(inputs) => hasher.update(inputs.as_bytes()),
+        None => {
+            return Err(MachinegenError::Precondition(String::from(
+                "The cloud-init seed image has not been built.",
+            )))
+        }
+    }
+
+    // Pulled images record their checksum, hashing ~600MiB every time is not an option
+    let mut checksum_path = base_image.as_os_str().to_owned();
+    checksum_path.push(".sha256");
+    let checksum = match fs::read_to_string(&checksum_path) {
+        Ok(content) => content
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        Err(_) => {
+            util::sha256_file(base_image).map_err(|error| MachinegenError::io(base_image, error))?
+        }
+    };
+    hasher.update(base_image.to_string_lossy().as_bytes());
+    hasher.update(checksum.as_bytes());
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Terraform project files, leaving out what Terraform itself writes in there.
+fn collect_project_files(folder: &Path, files: &mut Vec<PathBuf>) -> Result<(), io::Error> {
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        if terraform_owned(&name) || name.ends_with(".tfplan") {
+            continue;
+        }
+        if path.is_dir() {
+            collect_project_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Files Terraform keeps in its project: its providers, their lock and the state of what is
+/// deployed. Losing the state orphans the deployed machine, rebuilds must leave them alone.
+fn terraform_owned(name: &str) -> bool {
+    name == ".terraform" || name == ".terraform.lock.hcl" || name.starts_with("terraform.tfstate")
+}
+
+fn render_templates(
+    data: &MachineData,
+    config: &Value,
+    system: &System,
+    force: bool,
+) -> Result<Vec<PathBuf>, MachinegenError> {
+    let output = output_path(util::workspace(), system);
+    if force && output.exists() {
+        log::warning(&format!(
+            "Removing previously generated files in {}",
+            output.display()
+        ));
+        match system {
+            System::Guest => {
+                fs::remove_dir_all(&output).map_err(|error| MachinegenError::io(&output, error))?
+            }
+            System::Host => remove_generated(&output)?,
+        }
+    }
+
+    let written =
+        render::render_system(data, config, system, &util::workspace().config(), &output)?;
+    log::success(&format!(
+        "Rendered {} {} template{} into {}",
+        written.len(),
+        system.value(),
+        if written.len() == 1 { "" } else { "s" },
+        output.display()
+    ));
+    Ok(written)
+}
+
+/// Removes everything machinegen generated in the Terraform project, keeping what Terraform
+/// owns in there.
+fn remove_generated(project: &Path) -> Result<(), MachinegenError> {
+    let entries = fs::read_dir(project).map_err(|error| MachinegenError::io(project, error))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|error| MachinegenError::io(project, error))?
+            .path();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if terraform_owned(&name) {
+            log::debug(&format!("Keeping {}", path.display()));
+            continue;
+        }
+
+        if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        }
+        .map_err(|error| MachinegenError::io(&path, error))?;
+    }
+    Ok(())
+}
+
+/// Renders the guest templates and packs them into a NoCloud seed image.
+/// The image is only rebuilt when the rendered files change, or with `force`.
+fn cloud_init(data: &MachineData, config: &Value, force: bool) -> Result<(), MachinegenError> {
+    let written = render_templates(data, config, &System::Guest, force)?;
+    let output = output_path(util::workspace(), &System::Guest);
+
+    for path in &written {
+        let name = path.strip_prefix(&output).unwrap_or(path);
+        if !SEED_FILES.iter().any(|seed_file| name == *seed_file) {
+            log::warning(&format!(
+                "{} is not a cloud-init seed file ({}), it won't be part of the image.",
+                name.display(),
+                SEED_FILES.join(", ")
+            ));
+        }
+    }
+
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    for name in SEED_FILES {
+        let path = output.join(name);
+        if path.is_file() {
+            let content = fs::read(&path).map_err(|error| MachinegenError::io(&path, error))?;
+            files.push((name.to_string(), content));
+        }
+    }
+
+    if !files.iter().any(|(name, _)| name == "user-data") {
+        return Err(MachinegenError::Config(String::from(
+            "No guest template renders into user-data, the seed image can't be built.",
+        )));
+    }
+
+    let mut hasher = Sha256::new();
+    for (name, content) in &files {
+        hasher.update(name.as_bytes());
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(content);
+    }
+
+    // cloud-init refuses seeds without meta-data, a stable instance id is enough
+    if !files.iter().any(|(name, _)| name == "meta-data") {
+        let instance_id = format!("machinegen-{:x}", hasher.clone().finalize());
+        files.push((
+            String::from("meta-data"),
+            format!("instance-id: {}\n", &instance_id[..23]).into_bytes(),
+        ));
+    }
+    let inputs = format!("{:x}", hasher.finalize());
+
+    let seed = seed_path(util::workspace());
+    let lock_path = seed_lock_path(util::workspace());
+    let lock = util::read_lock(&lock_path);
+    if !force && seed.is_file() && lock.get("inputs") == Some(&inputs) {
+        log::info(&format!(
+            "Seed image {} is up to date, skipping.",
+            seed.display()
+        ));
+        return Ok(());
+    }
+
+    let image = iso::build("cidata", &files).map_err(MachinegenError::Image)?;
+
+    let mut partial = seed.clone().into_os_string();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    fs::write(&partial, image).map_err(|error| MachinegenError::io(&partial, error))?;
+    fs::rename(&partial, &seed).map_err(|error| MachinegenError::io(&seed, error))?;
+
+    util::write_lock(
+        &lock_path,
+        &[("iso", &seed.display().to_string()), ("inputs", &inputs)],
+    )
+    .map_err(|error| MachinegenError::io(&lock_path, error))?;
+
+    log::success(&format!(
+        "Built the cloud-init seed image at {}",
+        seed.display()
+    ));
+    Ok(())
+}
+
+/// Renders the host templates into a Terraform project, then initializes and plans it.
+/// The seed image and base image paths are handed to the project as variables.
+fn terraform_project(
+    data: &MachineData,
+    config: &Value,
+    image: Option<&str>,
+    force: bool,
+) -> Result<(), MachinegenError> {
+    let seed = seed_path(util::workspace());
+    if !seed.is_file() {
+        return Err(MachinegenError::Precondition(String::from(
+            "The cloud-init seed image is missing, build it first with `machinegen build --cloud`.",
+        )));
+    }
+    let base_image = pull::pulled_image(image)?;
+
+    // A failed build must not leave a previous plan ready to deploy
+    let _ = fs::remove_file(plan_lock_path(util::workspace()));
+
+    render_templates(data, config, &System::Host, force)?;
+    let project = terraform_path(util::workspace());
+
+    let variables = format!(
+        "# Generated by machinegen, do not edit.\n\n\
+        variable \"machinegen_seed_iso\" {{\n  \
+          description = \"Path of the cloud-init NoCloud seed image built by machinegen.\"\n  \
+          type        = string\n  \
+          default     = {}\n\
+        }}\n\n\
+        variable \"machinegen_base_image\" {{\n  \
+          description = \"Path of the base machine image pulled by machinegen.\"\n  \
+          type        = string\n  \
+          default     = {}\n\
+        }}\n\n\
+        variable \"machinegen_machine_name\" {{\n  \
+          description = \"Name of the machine in the machinegen workspace, to keep resource names apart.\"\n  \
+          type        = string\n  \
+          default     = \"{}\"\n\
+        }}\n",
+        quote(&seed),
+        quote(&base_image),
+        util::workspace().machine()
+    );
+    let variables_path = project.join("machinegen.tf");
+    fs::write(&variables_path, variables)
+        .map_err(|error| MachinegenError::io(&variables_path, error))?;
+
+    if !util::call_with_stdout(
+        util::terraform_command(&project).args(["init", "-input=false"]),
+        "Terraform project initialized.",
+        "terraform init failed, check its output above.",
+    ) {
+        return Err(MachinegenError::subprocess(
+            "terraform init",
+            "could not initialize the Terraform project",
+        ));
+    }
+
+    let _ = fs::remove_file(plan_path(util::workspace()));
+    if !util::call_with_stdout(
+        util::terraform_command(&project).args([
+            "plan",
+            "-input=false",
+            &format!("-out={}", PLAN_FILE),
+        ]),
+        "Terraform project planned.",
+        "terraform plan failed, check its output above.",
+    ) {
+        return Err(MachinegenError::subprocess(
+            "terraform plan",
+            "could not plan the Terraform project",
+        ));
+    }
+    if !plan_path(util::workspace()).is_file() {
+        return Err(MachinegenError::subprocess(
+            "terraform plan",
+            &format!(
+                "the plan file {} was not written",
+                plan_path(util::workspace()).display()
+            ),
+        ));
+    }
+
+    let inputs = plan_inputs(&base_image)?;
+    let lock_path = plan_lock_path(util::workspace());
+    util::write_lock(
+        &lock_path,
+        &[
+            ("plan", &plan_path(util::workspace()).display().to_string()),
+            ("base_image", &base_image.display().to_string()),
+            ("inputs", &inputs),
+        ],
+    )
+    .map_err(|error| MachinegenError::io(&lock_path, error))?;
+
+    log::success(&format!(
+        "Terraform plan saved at {}, deploy it with `machinegen deploy`.",
+        plan_path(util::workspace()).display()
+    ));
+    Ok(())
+}
+
+/// HCL string literal for a path.
+fn quote(path: &Path) -> String {
+    serde_json::to_string(&path.to_string_lossy())
+        .unwrap_or_else(|_| format!("\"{}\"", path.display()))
+}
@@ -0,0 +1,456 @@
+//! Minimal ISO 9660 writer with Joliet extensions, enough to build cloud-init NoCloud seeds
+//! without depending on `genisoimage` or `cloud-localds`.
+//!
+//! Only a flat root directory is supported. Files get both a primary (uppercase, restricted
+//! charset) and a Joliet (unicode, case preserving) name, the latter being what Linux uses.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECTOR: usize = 2048;
+
+// Fixed layout: system area, descriptors, path tables and both root directories
+const PRIMARY_DESCRIPTOR: usize = 16;
+const JOLIET_DESCRIPTOR: usize = 17;
+const TERMINATOR: usize = 18;
+const PRIMARY_L_PATH_TABLE: usize = 19;
+const PRIMARY_M_PATH_TABLE: usize = 20;
+const JOLIET_L_PATH_TABLE: usize = 21;
+const JOLIET_M_PATH_TABLE: usize = 22;
+const PRIMARY_ROOT: usize = 23;
+const JOLIET_ROOT: usize = 24;
+const FIRST_FILE: usize = 25;
+
+const PATH_TABLE_SIZE: u32 = 10;
+
+#[derive(Clone, Copy)]
+enum Naming {
+    Primary,
+    Joliet,
+}
+
+/// Builds an ISO image labeled `volume_id` holding `files` (name and content) in its root.
+pub fn build(volume_id: &str, files: &[(String, Vec<u8>)]) -> Result<Vec<u8>, String> {
+    let mut files: Vec<&(String, Vec<u8>)> = files.iter().collect();
+    files.sort_by(|left, right| left.0.cmp(&right.0));
+
+    // Place every file after the fixed layout
+    let mut extents: Vec<(u32, u32)> = Vec::new();
+    let mut next = FIRST_FILE;
+    for (_, content) in &files {
+        let length = u32::try_from(content.len())
+            .map_err(|_| String::from("files over 4GiB can't be stored in the seed image"))?;
+        extents.push((next as u32, length));
+        next += sectors(content.len());
+    }
+    let total = next;
+
+    let mut image = vec![0u8; total * SECTOR];
+    let date = RecordingDate::now();
+
+    for naming in [Naming::Primary, Naming::Joliet] {
+        let (root, descriptor, descriptor_type, l_table, m_table) = match naming {
+            Naming::Primary => (
+                PRIMARY_ROOT,
+                PRIMARY_DESCRIPTOR,
+                1,
+                PRIMARY_L_PATH_TABLE,
+                PRIMARY_M_PATH_TABLE,
+            ),
+            Naming::Joliet => (
+                JOLIET_ROOT,
+                JOLIET_DESCRIPTOR,
+                2,
+                JOLIET_L_PATH_TABLE,
+                JOLIET_M_PATH_TABLE,
+            ),
+        };
+
+        // Root directory extent: `.`, `..` and one record per file
+        let mut directory: Vec<u8> = Vec::new();
+        directory.extend(directory_record(
+            root as u32,
+            SECTOR as u32,
+            true,
+            &[0],
+            &date,
+        ));
+        directory.extend(directory_record(
+            root as u32,
+            SECTOR as u32,
+            true,
+            &[1],
+            &date,
+        ));
+        for ((name, _), (location, length)) in files.iter().zip(&extents) {
+            let identifier = file_identifier(name, naming)?;
+            directory.extend(directory_record(
+                *location,
+                *length,
+                false,
+                &identifier,
+                &date,
+            ));
+        }
+        if directory.len() > SECTOR {
+            return Err(String::from(
+                "too many files for the seed image root directory",
+            ));
+        }
+        write(&mut image, root, 0, &directory);
+
+        write(&mut image, l_table, 0, &path_table(root as u32, false));
+        write(&mut image, m_table, 0, &path_table(root as u32, true));
+
+        let mut volume = vec![0u8; SECTOR];
+        volume[0] = descriptor_type;
+        volume[1..6].copy_from_slice(b"CD001");
+        volume[6] = 1;
+        volume[8..40].copy_from_slice(&text("LINUX", 32, naming));
+        volume[40..72].copy_from_slice(&text(volume_id, 32, naming));
+        volume[80..88].copy_from_slice(&both_u32(total as u32));
+        if let Naming::Joliet = naming {
+            // UCS-2 level 3
+            volume[88..91].copy_from_slice(b"%/E");
+        }
+        volume[120..124].copy_from_slice(&both_u16(1));
+        volume[124..128].copy_from_slice(&both_u16(1));
+        volume[128..132].copy_from_slice(&both_u16(SECTOR as u16));
+        volume[132..140].copy_from_slice(&both_u32(PATH_TABLE_SIZE));
+        volume[140..144].copy_from_slice(&(l_table as u32).to_le_bytes());
+        volume[148..152].copy_from_slice(&(m_table as u32).to_be_bytes());
+        volume[156..190].copy_from_slice(&directory_record(
+            root as u32,
+            SECTOR as u32,
+            true,
+            &[0],
+            &date,
+        ));
+        volume[190..318].copy_from_slice(&text("", 128, naming));
+        volume[318..446].copy_from_slice(&text("", 128, naming));
+        volume[446..574].copy_from_slice(&text("", 128, naming));
+        volume[574..702].copy_from_slice(&text("MACHINEGEN", 128, naming));
+        volume[702..739].copy_from_slice(&text("", 37, naming));
+        volume[739..776].copy_from_slice(&text("", 37, naming));
+        volume[776..813].copy_from_slice(&text("", 37, naming));
+        volume[813..830].copy_from_slice(&date.descriptor_format());
+        volume[830..847].copy_from_slice(&date.descriptor_format());
+        volume[847..864].copy_from_slice(b"0000000000000000\0");
+        volume[864..881].copy_from_slice(&date.descriptor_format());
+        volume[881] = 1;
+        write(&mut image, descriptor, 0, &volume);
+    }
+
+    let mut terminator = vec![0u8; 7];
+    terminator[0] = 255;
+    terminator[1..6].copy_from_slice(b"CD001");
+    terminator[6] = 1;
+    write(&mut image, TERMINATOR, 0, &terminator);
+
+    for ((_, content), (location, _)) in files.iter().zip(&extents) {
+        write(&mut image, *location as usize, 0, content);
+    }
+
+    Ok(image)
+}
+
+fn sectors(length: usize) -> usize {
+    length.div_ceil(SECTOR)
+}
+
+fn write(image: &mut [u8], sector: usize, offset: usize, data: &[u8]) {
+    let start = sector * SECTOR + offset;
+    image[start..start + data.len()].copy_from_slice(data);
+}
+
+fn both_u16(value: u16) -> [u8; 4] {
+    let mut bytes = [0u8; 4];
+    bytes[..2].copy_from_slice(&value.to_le_bytes());
+    bytes[2..].copy_from_slice(&value.to_be_bytes());
+    bytes
+}
+
+fn both_u32(value: u32) -> [u8; 8] {
+    let mut bytes = [0u8; 8];
+    bytes[..4].copy_from_slice(&value.to_le_bytes());
+    bytes[4..].copy_from_slice(&value.to_be_bytes());
+    bytes
+}
+
+/// Space padded descriptor text, ASCII for the primary descriptor and UCS-2 for Joliet.
+fn text(value: &str, length: usize, naming: Naming) -> Vec<u8> {
+    let mut bytes: Vec<u8> = match naming {
+        Naming::Primary => value.bytes().take(length).collect(),
+        Naming::Joliet => value
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes)
+            .take(length - length % 2)
+            .collect(),
+    };
+    while bytes.len() < length {
+        match naming {
+            Naming::Primary => bytes.push(b' '),
+            Naming::Joliet if length - bytes.len() >= 2 => bytes.extend([0, b' ']),
+            Naming::Joliet => bytes.push(0),
+        }
+    }
+    bytes
+}
+
+fn file_identifier(name: &str, naming: Naming) -> Result<Vec<u8>, String> {
+    match naming {
+        Naming::Primary => {
+            // Level 2 names: up to 30 characters out of A-Z, 0-9 and _
+            let (stem, extension) = match name.rsplit_once('.') {
+                Some((stem, extension)) => (stem, extension),
+                None => (name, ""),
+            };
+            let clean = |part: &str| -> String {
+                part.chars()
+                    .map(|character| match character.to_ascii_uppercase() {
+                        upper @ ('A'..='Z' | '0'..='9' | '_') => upper,
+                        _ => '_',
+                    })
+                    .collect()
+            };
+            let mut stem = clean(stem);
+            let mut extension = clean(extension);
+            extension.truncate(30);
+            stem.truncate(30 - extension.len());
+            Ok(format!("{}.{};1", stem, extension).into_bytes())
+        }
+        Naming::Joliet => {
+            if name.encode_utf16().count() > 64 {
+                return Err(format!("{} is too long for the seed image", name));
+            }
+            Ok(format!("{};1", name)
+                .encode_utf16()
+                .flat_map(u16::to_be_bytes)
+                .collect())
+        }
+    }
+}
+
+fn directory_record(
+    location: u32,
+    length: u32,
+    directory: bool,
+    identifier: &[u8],
+    date: &RecordingDate,
+) -> Vec<u8> {
+    let mut record = vec![0u8; 33];
+    record[2..10].copy_from_slice(&both_u32(location));
+    record[10..18].copy_from_slice(&both_u32(length));
+    record[18..25].copy_from_slice(&date.record_format());
+    record[25] = if directory { 2 } else { 0 };
+    record[28..32].copy_from_slice(&both_u16(1));
+    record[32] = identifier.len() as u8;
+    record.extend_from_slice(identifier);
+    if record.len() % 2 == 1 {
+        record.push(0);
+    }
+    record[0] = record.len() as u8;
+    record
+}
+
+fn path_table(root: u32, big_endian: bool) -> Vec<u8> {
+    let mut table = vec![1u8, 0];
+    if big_endian {
+        table.extend(root.to_be_bytes());
+        table.extend(1u16.to_be_bytes());
+    } else {
+        table.extend(root.to_le_bytes());
+        table.extend(1u16.to_le_bytes());
+    }
+    table.extend([0, 0]);
+    table
+}
+
+/// UTC date, as both descriptor and directory records need it.
+struct RecordingDate {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+}
+
+impl RecordingDate {
+    fn now() -> RecordingDate {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or(0);
+        let days = seconds.div_euclid(86400);
+        let time = seconds.rem_euclid(86400) as u32;
+
+        // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+        let shifted = days + 719468;
+        let era = shifted.div_euclid(146097);
+        let day_of_era = shifted.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        } as u32;
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        RecordingDate {
+            year,
+            month,
+            day,
+            hour: time / 3600,
+            minute: time % 3600 / 60,
+            second: time % 60,
+        }
+    }
+
+    fn record_format(&self) -> [u8; 7] {
+        [
+            (self.year - 1900).clamp(0, 255) as u8,
+            self.month as u8,
+            self.day as u8,
+            self.hour as u8,
+            self.minute as u8,
+            self.second as u8,
+            0,
+        ]
+    }
+
+    fn descriptor_format(&self) -> [u8; 17] {
+        let mut bytes = [0u8; 17];
+        bytes[..16].copy_from_slice(
+            format!(
+                "{:04}{:02}{:02}{:02}{:02}{:02}00",
+                self.year, self.month, self.day, self.hour, self.minute, self.second
+            )
+            .as_bytes(),
+        );
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed() -> Vec<u8> {
+        build(
+            "cidata",
+            &[
+                (String::from("user-data"), b"#cloud-config\n".to_vec()),
+                (String::from("meta-data"), vec![b'm'; SECTOR + 1]),
+                (String::from("network-config"), Vec::new()),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn sector(image: &[u8], sector: usize) -> &[u8] {
+        &image[sector * SECTOR..(sector + 1) * SECTOR]
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        let little = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let big = u32::from_be_bytes(bytes[offset + 4..offset + 8].try_into().unwrap());
+        assert_eq!(little, big, "both endian copies must match");
+        little
+    }
+
+    /// Name, location and length of the file records of a root directory.
+    fn entries(directory: &[u8], joliet: bool) -> Vec<(String, u32, u32)> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while directory[offset] != 0 {
+            let record = &directory[offset..offset + directory[offset] as usize];
+            let identifier = &record[33..33 + record[32] as usize];
+            if record[25] & 2 == 0 {
+                let name = if joliet {
+                    let units: Vec<u16> = identifier
+                        .chunks(2)
+                        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                        .collect();
+                    String::from_utf16(&units).unwrap()
+                } else {
+                    String::from_utf8(identifier.to_vec()).unwrap()
+                };
+                entries.push((name, u32_at(record, 2), u32_at(record, 10)));
+            }
+            offset += record.len();
+        }
+        entries
+    }
+
+    #[test]
+    fn descriptors() {
+        let image = seed();
+        // Fixed layout, then one sector for user-data, two for meta-data and none for network-config
+        assert_eq!(image.len(), (FIRST_FILE + 3) * SECTOR);
+
+        let primary = sector(&image, PRIMARY_DESCRIPTOR);
+        assert_eq!(&primary[..7], b"\x01CD001\x01");
+        assert_eq!(&primary[40..46], b"cidata");
+        assert_eq!(u32_at(primary, 80) as usize, FIRST_FILE + 3);
+
+        let joliet = sector(&image, JOLIET_DESCRIPTOR);
+        assert_eq!(&joliet[..7], b"\x02CD001\x01");
+        assert_eq!(&joliet[88..91], b"%/E");
+        assert_eq!(&joliet[40..52], &text("cidata", 12, Naming::Joliet)[..]);
+
+        assert_eq!(&sector(&image, TERMINATOR)[..7], b"\xffCD001\x01");
+    }
+
+    #[test]
+    fn files() {
+        let image = seed();
+        let joliet = entries(sector(&image, JOLIET_ROOT), true);
+        assert_eq!(
+            joliet,
+            vec![
+                (
+                    String::from("meta-data;1"),
+                    FIRST_FILE as u32,
+                    SECTOR as u32 + 1
+                ),
+                (String::from("network-config;1"), FIRST_FILE as u32 + 2, 0),
+                (String::from("user-data;1"), FIRST_FILE as u32 + 2, 14),
+            ]
+        );
+        let names: Vec<String> = entries(sector(&image, PRIMARY_ROOT), false)
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .collect();
+        assert_eq!(names, ["META_DATA.;1", "NETWORK_CONFIG.;1", "USER_DATA.;1"]);
+
+        let user_data = &image[(FIRST_FILE + 2) * SECTOR..];
+        assert_eq!(&user_data[..14], b"#cloud-config\n");
+        assert!(image[FIRST_FILE * SECTOR..(FIRST_FILE + 1) * SECTOR + 1]
+            .iter()
+            .all(|&byte| byte == b'm'));
+    }
+
+    #[test]
+    fn primary_names() {
+        let name = |name: &str| {
+            String::from_utf8(file_identifier(name, Naming::Primary).unwrap()).unwrap()
+        };
+        assert_eq!(name("vendor-data"), "VENDOR_DATA.;1");
+        assert_eq!(name("init.sh"), "INIT.SH;1");
+        assert_eq!(name("a.b.tar.gz"), "A_B_TAR.GZ;1");
+        assert_eq!(name(&"x".repeat(40)), format!("{}.;1", "X".repeat(30)));
+    }
+
+    #[test]
+    fn rejected_files() {
+        assert!(build("cidata", &[("x".repeat(65), Vec::new())]).is_err());
+        let many: Vec<(String, Vec<u8>)> = (0..100)
+            .map(|index| (format!("file-{:03}", index), Vec::new()))
+            .collect();
+        assert!(build("cidata", &many).is_err());
+    }
+}
@@ -0,0 +1,17 @@
+use machinegen::check;
+
+use super::types::{MachinegenError, TableError};
+use super::{log, util};
+
+pub fn run(_sub_match: &clap::ArgMatches) -> Result<(), MachinegenError> {
+    let problems = check::check(&util::workspace().config())?;
+
+    if problems.is_empty() {
+        log::success("Machine config tables are consistent.");
+        return Ok(());
+    }
+    Err(MachinegenError::Table {
+        path: util::workspace().tables(),
+        error: TableError::Parsing(problems),
+    })
+}
@@ -0,0 +1,216 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::types::MachinegenError;
+use super::workspace::Workspace;
+use super::{deploy, log, util};
+
+pub fn run(sub_match: &clap::ArgMatches) -> Result<(), MachinegenError> {
+    let dry_run = sub_match.contains_id("dry-run");
+    let force = sub_match.contains_id("force");
+    let all = sub_match.contains_id("all");
+
+    let mut targets: BTreeSet<PathBuf> = BTreeSet::new();
+
+    if all || sub_match.contains_id("config") {
+        let user = sub_match.contains_id("user");
+        let machine = sub_match.contains_id("machine");
+        if all || user == machine {
+            targets.insert(util::workspace().config());
+            targets.insert(util::workspace().config_lock());
+            targets.extend(config_entries(true)?);
+        } else {
+            targets.extend(config_entries(user)?);
+            if machine {
+                targets.insert(util::workspace().config_lock());
+            }
+        }
+    }
+
+    if all || sub_match.contains_id("deps") {
+        let image = sub_match.contains_id("image");
+        let runtime = sub_match.contains_id("runtime");
+        if all || image == runtime {
+            targets.insert(util::workspace().deps());
+        } else if image {
+            targets.insert(util::workspace().images());
+        } else {
+            targets.extend(runtime_entries()?);
+        }
+    }
+
+    let generated = all || sub_match.contains_id("generated");
+    if generated {
+        targets.insert(util::workspace().build());
+        targets.insert(util::workspace().state());
+    }
+    if all {
+        targets.insert(util::workspace().path(&["machines"]));
+    }
+
+    // Without its state, Terraform can't tell what it deployed anymore
+    if generated {
+        let workspace = util::workspace();
+        let machines = if all {
+            workspace.machines()?
+        } else {
+            vec![workspace.machine().to_string()]
+        };
+        for machine in machines {
+            let message = match live_deployment(&workspace.with_machine(&machine)?)? {
+                Some(message) => message,
+                None => continue,
+            };
+            let message = format!("Machine `{}`: {}", machine, message);
+            if force {
+                log::warning(&format!("{} Deleting it anyway.", message));
+            } else if dry_run {
+                log::warning(&format!("{} This clean requires --force.", message));
+            } else {
+                return Err(MachinegenError::Precondition(format!(
+                    "{} Use --force to delete it anyway.",
+                    message
+                )));
+            }
+        }
+    }
+
+    let mut freed: u64 = 0;
+    let mut removed: usize = 0;
+    // Entries inside another target go along with it
+    for target in targets
+        .iter()
+        .filter(|target| target.symlink_metadata().is_ok())
+        .filter(|target| {
+            !target
+                .ancestors()
+                .skip(1)
+                .any(|ancestor| targets.contains(ancestor))
+        })
+    {
+        let size = disk_usage(target).map_err(|error| MachinegenError::io(target, error))?;
+        freed += size;
+        removed += 1;
+
+        if dry_run {
+            log::info(&format!(
+                "Would remove {} ({})",
+                target.display(),
+                util::human_size(size)
+            ));
+            continue;
+        }
+
+        let result = if target.is_dir() && !target.is_symlink() {
+            fs::remove_dir_all(target)
+        } else {
+            fs::remove_file(target)
+        };
+        result.map_err(|error| MachinegenError::io(target, error))?;
+        log::info(&format!(
+            "Removed {} ({})",
+            target.display(),
+            util::human_size(size)
+        ));
+    }
+
+    if removed == 0 {
+        log::info("Nothing to clean.");
+    } else if dry_run {
+        log::success(&format!("Cleaning would free {}.", util::human_size(freed)));
+    } else {
+        // Leave no empty machine or workspace behind
+        if util::workspace().machine_root() != util::workspace().root() {
+            let _ = fs::remove_dir(util::workspace().machine_root());
+        }
+        if all {
+            let _ = fs::remove_dir(util::workspace().root());
+        }
+        log::success(&format!("Freed {}.", util::human_size(freed)));
+    }
+    Ok(())
+}
+
+/// Entries belonging to the user config of the machine, or to the machine config.
+/// The user config is made of the `user.*` files, living next to the machine config
+/// for the default machine, and next to the build and state of named ones.
+fn config_entries(user: bool) -> Result<Vec<PathBuf>, MachinegenError> {
+    let folder = if user {
+        util::workspace().user()
+    } else {
+        util::workspace().config()
+    };
+    if !folder.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(&folder).map_err(|error| MachinegenError::io(&folder, error))? {
+        let path = entry
+            .map_err(|error| MachinegenError::io(&folder, error))?
+            .path();
+        let is_user = path
+            .file_name()
+            .map(|name| name.to_string_lossy().starts_with("user."))
+            .unwrap_or(false);
+        if is_user == user {
+            entries.push(path);
+        }
+    }
+    Ok(entries)
+}
+
+/// What is still deployed for the machine of `workspace`, if anything.
+fn live_deployment(workspace: &Workspace) -> Result<Option<String>, MachinegenError> {
+    let resources = deploy::tracked_resources(workspace)?;
+    Ok(if !resources.is_empty() {
+        Some(format!(
+            "The Terraform state still tracks {} live resource{} ({}), destroy the deployment first with `machinegen destroy`.",
+            resources.len(),
+            if resources.len() == 1 { "" } else { "s" },
+            resources.join(", ")
+        ))
+    } else if deploy::recorded_as_deployed(workspace) {
+        Some(String::from(
+            "The deployment record says the machine is still deployed, destroy it first with `machinegen destroy`.",
+        ))
+    } else {
+        None
+    })
+}
+
+/// Runtime dependencies, everything in the deps folder but the images cache.
+fn runtime_entries() -> Result<Vec<PathBuf>, MachinegenError> {
+    let folder = util::workspace().deps();
+    if !folder.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let images = util::workspace().images();
+    let mut entries: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(&folder).map_err(|error| MachinegenError::io(&folder, error))? {
+        let path = entry
+            .map_err(|error| MachinegenError::io(&folder, error))?
+            .path();
+        if path != images {
+            entries.push(path);
+        }
+    }
+    Ok(entries)
+}
+
+/// Size of a file or folder tree, symlinks counting as themselves.
+fn disk_usage(path: &Path) -> Result<u64, io::Error> {
+    let metadata = path.symlink_metadata()?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut size: u64 = 0;
+    for entry in fs::read_dir(path)? {
+        size += disk_usage(&entry?.path())?;
+    }
+    Ok(size)
+}
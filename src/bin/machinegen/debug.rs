@@ -0,0 +1,153 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use super::types::{ConfigEntry, MachinegenError, Records, TableTypes};
+use super::{log, render, user_config, util};
+
+pub fn run(sub_match: &clap::ArgMatches) -> Result<(), MachinegenError> {
+    let format = sub_match
+        .get_one::<String>("format")
+        .map(String::as_str)
+        .unwrap_or("json");
+
+    match sub_match.subcommand() {
+        Some(("table", table_match)) => table(table_match, format),
+        Some(("data", _)) => print(&util::process_relations()?, format),
+        Some(("config", _)) => config(format),
+        Some(("replacements", replacements_match)) => replacements(replacements_match, format),
+        _ => unreachable!(),
+    }
+}
+
+/// Writes `value` to stdout as pretty JSON, single line JSON or Rust debug output.
+fn print<T: Serialize + Debug>(value: &T, format: &str) -> Result<(), MachinegenError> {
+    let output = match format {
+        "debug" => format!("{:#?}", value),
+        // Going through a `Value` sorts the keys of maps, which are hash maps in the machine data
+        _ => {
+            let value = serde_json::to_value(value).map_err(MachinegenError::Serialization)?;
+            let output = if format == "compact" {
+                serde_json::to_string(&value)
+            } else {
+                serde_json::to_string_pretty(&value)
+            };
+            output.map_err(MachinegenError::Serialization)?
+        }
+    };
+    println!("{}", output);
+    Ok(())
+}
+
+fn table(table_match: &clap::ArgMatches, format: &str) -> Result<(), MachinegenError> {
+    let table_type = match table_match.get_one::<String>("table").map(String::as_str) {
+        Some("replace") => TableTypes::Replace,
+        Some("files") => TableTypes::Files,
+        Some("templates") => TableTypes::Template,
+        Some("dependencies") => TableTypes::Dependencies,
+        Some("images") => TableTypes::Images,
+        _ => unreachable!(),
+    };
+
+    let records = util::load_table(table_type)?;
+    if format == "debug" {
+        println!("{:#?}", records);
+        return Ok(());
+    }
+    let rows: Vec<Value> = records
+        .iter()
+        .map(|record| match record {
+            Records::Replace(record) => serde_json::to_value(record),
+            Records::Template(record) => serde_json::to_value(record),
+            Records::Files(record) => serde_json::to_value(record),
+            Records::Dependency(record) => serde_json::to_value(record),
+            Records::Image(record) => serde_json::to_value(record),
+        })
+        .collect::<Result<_, _>>()
+        .map_err(MachinegenError::Serialization)?;
+    print(&rows, format)
+}
+
+/// The config keys as related out of the tables, along with the values the user config
+/// gives them, if there's a readable user config.
+fn config(format: &str) -> Result<(), MachinegenError> {
+    let data = util::process_relations()?;
+    let user = load_user_config();
+    print(&config_tree(&data.config_keys, user.as_ref()), format)
+}
+
+fn config_tree(entries: &HashMap<String, ConfigEntry>, user: Option<&Value>) -> Value {
+    let mut tree = Map::new();
+    for (key, entry) in entries {
+        let user = user.and_then(|user| user.get(key));
+        let mut node = json!({
+            "description": entry.description,
+            "mandatory": entry.mandatory,
+            "unique": entry.unique,
+        });
+        match &entry.children {
+            Some(children) => node["children"] = config_tree(children, user),
+            None => node["value"] = user.cloned().unwrap_or(Value::Null),
+        }
+        tree.insert(key.clone(), node);
+    }
+    Value::Object(tree)
+}
+
+/// For every template, or just the given one, what each placeholder gets replaced with.
+fn replacements(
+    replacements_match: &clap::ArgMatches,
+    format: &str,
+) -> Result<(), MachinegenError> {
+    let data = util::process_relations()?;
+    let config = load_user_config();
+
+    let name = replacements_match.get_one::<String>("template");
+    if let Some(name) = name {
+        if !data.templates.contains_key(name) {
+            return Err(MachinegenError::Config(format!(
+                "There is no template `{}` in the templates table.",
+                name
+            )));
+        }
+    }
+
+    let mut map = Map::new();
+    for (template_name, template) in &data.templates {
+        if name.map(|name| name != template_name).unwrap_or(false) {
+            continue;
+        }
+        let values = config
+            .as_ref()
+            .map(|config| render::replacements(template, config));
+
+        let mut placeholders = Map::new();
+        for (key, entry) in &template.replacements {
+            let mut node = json!({
+                "config_path": key,
+                "mandatory": entry.mandatory,
+                "unique": entry.unique,
+            });
+            match values.as_ref().and_then(|values| values.get(key.as_str())) {
+                Some(Ok(value)) => node["value"] = json!(value),
+                Some(Err(error)) => node["error"] = json!(error),
+                None => node["value"] = Value::Null,
+            }
+            placeholders.insert(key.clone(), node);
+        }
+        map.insert(template_name.clone(), Value::Object(placeholders));
+    }
+    print(&Value::Object(map), format)
+}
+
+/// The user config, when there's one that can be read. Debugging the tables doesn't need it.
+fn load_user_config() -> Option<Value> {
+    match user_config::load(&util::workspace().user()) {
+        Ok(config) => Some(config),
+        Err(error) => {
+            log::debug(&format!("Leaving the user config out: {}", error));
+            None
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::types::MachinegenError;
+use super::workspace::Workspace;
+use super::{build, log, util};
+
+/// What a plan will do to the resources it touches.
+#[derive(Default)]
+pub struct PlanSummary {
+    pub changes: Vec<(&'static str, String)>,
+    pub add: usize,
+    pub change: usize,
+    pub destroy: usize,
+}
+
+pub fn run(sub_match: &clap::ArgMatches) -> Result<(), MachinegenError> {
+    let yes = sub_match.contains_id("yes");
+
+    let plan = build::plan_path(util::workspace());
+    let lock = util::read_lock(&build::plan_lock_path(util::workspace()));
+    let (base_image, inputs) = match (lock.get("base_image"), lock.get("inputs")) {
+        (Some(base_image), Some(inputs)) if plan.is_file() => (PathBuf::from(base_image), inputs),
+        _ => return Err(MachinegenError::Precondition(String::from(
+            "There is no Terraform plan to deploy, build one with `machinegen build --terraform`.",
+        ))),
+    };
+
+    // Applying a plan made out of other inputs would deploy something nobody reviewed
+    if build::plan_inputs(&base_image)? != *inputs {
+        return Err(MachinegenError::Precondition(String::from(
+            "The Terraform project, seed image or base image changed since the plan was made, \
+            run `machinegen build --terraform` again.",
+        )));
+    }
+
+    let project = build::terraform_path(util::workspace());
+    let summary = summarize(&show_plan(&project)?);
+
+    if summary.changes.is_empty() {
+        log::info("The plan has no resource changes.");
+    } else {
+        print_summary(&summary);
+        if !yes && !util::confirm("Apply this plan?")? {
+            log::warning("Deployment cancelled.");
+            return Ok(());
+        }
+    }
+
+    if !util::call_with_stdout(
+        util::terraform_command(&project).args(["apply", "-input=false", build::PLAN_FILE]),
+        "Terraform plan applied.",
+        "terraform apply failed, check its output above.",
+    ) {
+        return Err(MachinegenError::subprocess(
+            "terraform apply",
+            "could not deploy the Terraform project",
+        ));
+    }
+
+    // A saved plan can only be applied once
+    let _ = fs::remove_file(build::plan_lock_path(util::workspace()));
+
+    let outputs = outputs(&project)?;
+    let record = json!({
+        "status": "deployed",
+        "deployed_at": SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0),
+        "inputs": inputs,
+        "base_image": base_image,
+        "outputs": outputs,
+    });
+    write_state(&record)?;
+
+    for (name, value) in &outputs {
+        log::info(&format!("{} = {}", name, value));
+    }
+    log::success(&format!(
+        "Deployment recorded in {}",
+        state_path(util::workspace()).display()
+    ));
+    Ok(())
+}
+
+/// Deployment record, with the outputs of the last successful apply.
+pub fn state_path(workspace: &Workspace) -> PathBuf {
+    workspace.state().join("deployment.json")
+}
+
+pub fn read_state(workspace: &Workspace) -> Option<Value> {
+    let content = fs::read_to_string(state_path(workspace)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Whether the deployment record says something is deployed.
+pub fn recorded_as_deployed(workspace: &Workspace) -> bool {
+    read_state(workspace)
+        .and_then(|record| record.get("status").cloned())
+        .map(|status| status == "deployed")
+        .unwrap_or(false)
+}
+
+pub fn write_state(record: &Value) -> Result<(), MachinegenError> {
+    let path = state_path(util::workspace());
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|error| MachinegenError::io(parent, error))?;
+    }
+    let content = serde_json::to_string_pretty(record).map_err(MachinegenError::Serialization)?;
+    fs::write(&path, content + "\n").map_err(|error| MachinegenError::io(&path, error))
+}
+
+/// Resources the Terraform state of the project still tracks, data sources aside.
+/// Anything counted here exists outside of machinegen and is lost track of if the state goes.
+pub fn tracked_resources(workspace: &Workspace) -> Result<Vec<String>, MachinegenError> {
+    let path = build::terraform_path(workspace).join("terraform.tfstate");
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let state: Value =
+        serde_json::from_str(&content).map_err(|error| MachinegenError::io(&path, error.into()))?;
+
+    Ok(state
+        .get("resources")
+        .and_then(Value::as_array)
+        .map(|resources| {
+            resources
+                .iter()
+                .filter(|resource| resource.get("mode").and_then(Value::as_str) == Some("managed"))
+                .map(|resource| {
+                    format!(
+                        "{}.{}",
+                        resource.get("type").and_then(Value::as_str).unwrap_or("?"),
+                        resource.get("name").and_then(Value::as_str).unwrap_or("?")
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Runs a terraform command in `project`, returning its JSON output.
+pub fn terraform_json(project: &Path, args: &[&str]) -> Result<Value, MachinegenError> {
+    let command = format!("terraform {}", args.join(" "));
+    let output = util::terraform_command(project)
+        .args(args)
+        .output()
+        .map_err(|error| MachinegenError::subprocess(&command, &error.to_string()))?;
+
+    if !output.status.success() {
+        return Err(MachinegenError::subprocess(
+            &command,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ));
+    }
+    serde_json::from_slice(&output.stdout).map_err(|error| {
+        MachinegenError::subprocess(&command, &format!("unexpected output: {}", error))
+    })
+}
+
+fn show_plan(project: &Path) -> Result<Value, MachinegenError> {
+    terraform_json(project, &["show", "-json", build::PLAN_FILE])
+}
+
+/// Output values of the applied project, by name.
+fn outputs(project: &Path) -> Result<Map<String, Value>, MachinegenError> {
+    let outputs = terraform_json(project, &["output", "-json"])?;
+    Ok(outputs
+        .as_object()
+        .map(|outputs| {
+            outputs
+                .iter()
+                .map(|(name, output)| {
+                    (
+                        name.clone(),
+                        output.get("value").cloned().unwrap_or(Value::Null),
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+pub fn print_summary(summary: &PlanSummary) {
+    log::info("Terraform will perform the following actions:");
+    for (symbol, address) in &summary.changes {
+        log::info(&format!("  {} {}", symbol, address));
+    }
+    log::info(&format!(
+        "Plan: {} to add, {} to change, {} to destroy.",
+        summary.add, summary.change, summary.destroy
+    ));
+}
+
+/// Counts resource changes the way `terraform plan` does, replacements adding and destroying.
+pub fn summarize(plan: &Value) -> PlanSummary {
+    let mut summary = PlanSummary::default();
+
+    let changes = plan
+        .get("resource_changes")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+
+    for change in changes {
+        let address = change
+            .get("address")
+            .and_then(Value::as_str)
+            .unwrap_or("(unknown)")
+            .to_string();
+        let actions: Vec<&str> = change
+            .pointer("/change/actions")
+            .and_then(Value::as_array)
+            .map(|actions| actions.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+
+        let symbol = match actions.as_slice() {
+            ["create"] => {
+                summary.add += 1;
+                "+"
+            }
+            ["update"] => {
+                summary.change += 1;
+                "~"
+            }
+            ["delete"] => {
+                summary.destroy += 1;
+                "-"
+            }
+            ["delete", "create"] | ["create", "delete"] => {
+                summary.add += 1;
+                summary.destroy += 1;
+                "-/+"
+            }
+            _ => continue,
+        };
+        summary.changes.push((symbol, address));
+    }
+    summary
+}
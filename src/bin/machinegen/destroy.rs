@@ -0,0 +1,132 @@
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::types::MachinegenError;
+use super::{build, deploy, log, util};
+
+/// Destroy plan, made and applied in one go so what gets removed is what was shown.
+const DESTROY_PLAN_FILE: &str = "machinegen-destroy.tfplan";
+
+pub fn run(sub_match: &clap::ArgMatches) -> Result<(), MachinegenError> {
+    let yes = sub_match.contains_id("yes");
+    let project = build::terraform_path(util::workspace());
+
+    let tracked = deploy::tracked_resources(util::workspace())?;
+    if tracked.is_empty() {
+        if deploy::recorded_as_deployed(util::workspace()) {
+            log::warning("The deployment record says the machine is deployed, but the Terraform state tracks no resources. \
+                They may have to be removed by hand.",
+            );
+            record_destroyed(&[])?;
+        } else {
+            log::info("Nothing is deployed, there is nothing to destroy.");
+        }
+        return Ok(());
+    }
+
+    let plan_path = project.join(DESTROY_PLAN_FILE);
+    let _ = fs::remove_file(&plan_path);
+    let planned = util::call_with_stdout(
+        util::terraform_command(&project).args([
+            "plan",
+            "-destroy",
+            "-input=false",
+            &format!("-out={}", DESTROY_PLAN_FILE),
+        ]),
+        "Terraform destroy plan made.",
+        "terraform plan -destroy failed, check its output above.",
+    );
+    if !planned || !plan_path.is_file() {
+        return Err(MachinegenError::subprocess(
+            "terraform plan -destroy",
+            "could not plan the destruction of the deployment",
+        ));
+    }
+
+    let result = destroy(&project, yes);
+    let _ = fs::remove_file(&plan_path);
+    result
+}
+
+fn destroy(project: &Path, yes: bool) -> Result<(), MachinegenError> {
+    let plan = deploy::terraform_json(project, &["show", "-json", DESTROY_PLAN_FILE])?;
+    let drift = removed_out_of_band(&plan);
+    for address in &drift {
+        log::warning(&format!(
+            "{} was removed outside of Terraform, it's only left in the state.",
+            address
+        ));
+    }
+
+    let summary = deploy::summarize(&plan);
+    if summary.changes.is_empty() {
+        log::info("No resources are left to destroy, only the Terraform state will be updated.");
+    } else {
+        deploy::print_summary(&summary);
+        if !yes && !util::confirm("Destroy these resources?")? {
+            log::warning("Destruction cancelled.");
+            return Ok(());
+        }
+    }
+
+    if !util::call_with_stdout(
+        util::terraform_command(project).args(["apply", "-input=false", DESTROY_PLAN_FILE]),
+        "Terraform destroy plan applied.",
+        "terraform apply failed, check its output above.",
+    ) {
+        return Err(MachinegenError::subprocess(
+            "terraform apply",
+            "could not destroy the deployment",
+        ));
+    }
+
+    let left = deploy::tracked_resources(util::workspace())?;
+    if !left.is_empty() {
+        return Err(MachinegenError::subprocess(
+            "terraform apply",
+            &format!(
+                "the Terraform state still tracks {} after destroying, check the Terraform project",
+                left.join(", ")
+            ),
+        ));
+    }
+
+    record_destroyed(&drift)?;
+    log::success(&format!(
+        "Deployment destroyed, recorded in {}",
+        deploy::state_path(util::workspace()).display()
+    ));
+    Ok(())
+}
+
+/// Resources the refresh before planning found gone, like a libvirt domain undefined by hand.
+fn removed_out_of_band(plan: &Value) -> Vec<String> {
+    plan.get("resource_drift")
+        .and_then(Value::as_array)
+        .map(|drift| {
+            drift
+                .iter()
+                .filter(|resource| resource.pointer("/change/actions") == Some(&json!(["delete"])))
+                .filter_map(|resource| resource.get("address").and_then(Value::as_str))
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn record_destroyed(drift: &[String]) -> Result<(), MachinegenError> {
+    let mut record = deploy::read_state(util::workspace()).unwrap_or_else(|| json!({}));
+    if !record.is_object() {
+        record = json!({});
+    }
+    record["status"] = json!("destroyed");
+    record["destroyed_at"] = json!(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0));
+    record["outputs"] = json!({});
+    record["drift"] = json!(drift);
+    deploy::write_state(&record)
+}
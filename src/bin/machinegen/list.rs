@@ -0,0 +1,109 @@
+use serde_json::Value;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::types::MachinegenError;
+use super::workspace::Workspace;
+use super::{build, deploy, user_config, util};
+
+pub fn run(_sub_match: &clap::ArgMatches) -> Result<(), MachinegenError> {
+    let workspace = util::workspace();
+
+    let mut rows: Vec<[String; 4]> = Vec::new();
+    for name in workspace.machines()? {
+        let machine = workspace.with_machine(&name)?;
+        rows.push([
+            name,
+            user_config_status(&machine),
+            build_status(&machine),
+            deployment_status(&machine),
+        ]);
+    }
+
+    let header = [
+        String::from("MACHINE"),
+        String::from("USER CONFIG"),
+        String::from("BUILD"),
+        String::from("DEPLOYMENT"),
+    ];
+    let mut widths = [0; 4];
+    for row in rows.iter().chain([&header]) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in [&header].into_iter().chain(&rows) {
+        println!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        );
+    }
+    Ok(())
+}
+
+fn user_config_status(machine: &Workspace) -> String {
+    match user_config::find(&machine.user()) {
+        Some((path, _)) => path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        None => String::from("missing"),
+    }
+}
+
+fn build_status(machine: &Workspace) -> String {
+    String::from(if build::plan_lock_path(machine).is_file() {
+        "planned"
+    } else if build::seed_path(machine).is_file() {
+        "seed image built"
+    } else if machine.build().is_dir() {
+        "rendered"
+    } else {
+        "not built"
+    })
+}
+
+fn deployment_status(machine: &Workspace) -> String {
+    let record = match deploy::read_state(machine) {
+        Some(record) => record,
+        None => return String::from("never deployed"),
+    };
+    let status = record
+        .get("status")
+        .and_then(Value::as_str)
+        .unwrap_or("unknown");
+    match record
+        .get(format!("{}_at", status).as_str())
+        .and_then(Value::as_u64)
+    {
+        Some(at) => format!("{} {}", status, ago(at)),
+        None => status.to_string(),
+    }
+}
+
+/// How long ago a Unix timestamp was, roughly.
+fn ago(timestamp: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let seconds = now.saturating_sub(timestamp);
+
+    let (amount, unit) = match seconds {
+        0..=59 => return String::from("just now"),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    format!(
+        "{} {}{} ago",
+        amount,
+        unit,
+        if amount == 1 { "" } else { "s" }
+    )
+}
@@ -0,0 +1,257 @@
+use colored::*;
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::types::MachinegenError;
+
+/// Lines of a failed subprocess output repeated on the terminal when it was captured.
+const FAILURE_TAIL: usize = 20;
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+    Success,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Success => "success",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    /// Verbosity from which records of this level get to the terminal, 0 being the default.
+    fn verbosity(&self) -> i8 {
+        match self {
+            Level::Error => -2,
+            Level::Warning => -1,
+            Level::Success | Level::Info => 0,
+            Level::Debug => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+struct Logger {
+    verbosity: i8,
+    format: Format,
+    file: Option<Mutex<File>>,
+}
+
+const DEFAULT: Logger = Logger {
+    verbosity: 0,
+    format: Format::Text,
+    file: None,
+};
+
+fn logger() -> &'static Logger {
+    LOGGER.get().unwrap_or(&DEFAULT)
+}
+
+/// Sets up logging, once, before running any command. Until then, records go to the
+/// terminal as text at the default verbosity.
+///
+/// `verbosity` is the number of `-v` minus the number of `-q`. Records are written in `format`,
+/// errors and warnings to stderr and everything else to stdout. The log file, if any, gets every
+/// record whatever the verbosity, along with the full output of the programs machinegen runs.
+pub fn init(verbosity: i8, format: Format, file: Option<&Path>) -> Result<(), MachinegenError> {
+    let file = match file {
+        Some(path) => Some(Mutex::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|error| MachinegenError::io(path, error))?,
+        )),
+        None => None,
+    };
+    let _ = LOGGER.set(Logger {
+        verbosity,
+        format,
+        file,
+    });
+    Ok(())
+}
+
+/// Whether records of `level` get to the terminal.
+pub fn enabled(level: Level) -> bool {
+    level.verbosity() <= logger().verbosity
+}
+
+/// Whether the terminal shows human readable text, progress lines included.
+pub fn interactive() -> bool {
+    logger().format == Format::Text && enabled(Level::Info)
+}
+
+pub fn error(message: &str) {
+    log(Level::Error, message);
+}
+
+pub fn warning(message: &str) {
+    log(Level::Warning, message);
+}
+
+pub fn success(message: &str) {
+    log(Level::Success, message);
+}
+
+pub fn info(message: &str) {
+    log(Level::Info, message);
+}
+
+pub fn debug(message: &str) {
+    log(Level::Debug, message);
+}
+
+pub fn log(level: Level, message: &str) {
+    record(level, None, message);
+}
+
+fn record(level: Level, source: Option<&str>, message: &str) {
+    let logger = logger();
+    if let Some(file) = &logger.file {
+        if let Ok(mut file) = file.lock() {
+            let _ = writeln!(file, "{}", plain(logger.format, level, source, message));
+        }
+    }
+    terminal(level, source, message);
+}
+
+fn terminal(level: Level, source: Option<&str>, message: &str) {
+    let logger = logger();
+    if !enabled(level) {
+        return;
+    }
+    let line = match logger.format {
+        Format::Json => plain(Format::Json, level, source, message),
+        Format::Text => colored(level, source, message),
+    };
+    // Nowhere left to report a failing terminal to
+    let _ = match level {
+        Level::Error | Level::Warning => writeln!(io::stderr(), "{}", line),
+        _ => writeln!(io::stdout(), "{}", line),
+    };
+}
+
+fn plain(format: Format, level: Level, source: Option<&str>, message: &str) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs_f64())
+        .unwrap_or_default();
+
+    match format {
+        Format::Json => {
+            let mut record = json!({
+                "timestamp": timestamp,
+                "level": level.name(),
+                "message": message,
+            });
+            if let Some(source) = source {
+                record["source"] = json!(source);
+            }
+            record.to_string()
+        }
+        Format::Text => match source {
+            Some(source) => format!(
+                "{:.3} {:<7} [{}] {}",
+                timestamp,
+                level.name(),
+                source,
+                message
+            ),
+            None => format!("{:.3} {:<7} {}", timestamp, level.name(), message),
+        },
+    }
+}
+
+fn colored(level: Level, source: Option<&str>, message: &str) -> String {
+    let message = match source {
+        Some(source) => format!("[{}] {}", source, message),
+        None => message.to_string(),
+    };
+    match level {
+        Level::Info => format!(
+            "{} {}",
+            "[machinegen]".bright_blue().bold(),
+            message.bright_blue()
+        ),
+        Level::Debug => format!(
+            "{}{} {}",
+            "[machinegen]".bright_purple().bold(),
+            "[debug]".yellow().bold(),
+            message.italic().yellow()
+        ),
+        Level::Error => format!(
+            "{} {}",
+            "[machinegen]".bright_red().bold(),
+            message.bright_red().bold()
+        ),
+        Level::Warning => format!("{} {}", "[machinegen]".yellow().bold(), message.yellow()),
+        Level::Success => format!(
+            "{} {}",
+            "[machinegen]".bright_green().bold(),
+            message.bright_green()
+        ),
+    }
+}
+
+/// Runs `command` to completion. Its output goes straight to the terminal, unless there's a
+/// log file, JSON records or a quiet terminal to keep clean: then it's captured into the log
+/// file and debug records, and only the end of it is repeated as errors if the command fails.
+pub fn run(command: &mut Command) -> Result<ExitStatus, io::Error> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let arguments: Vec<String> = command
+        .get_args()
+        .map(|argument| argument.to_string_lossy().to_string())
+        .collect();
+    debug(&format!("Running {} {}", program, arguments.join(" ")));
+
+    let logger = logger();
+    if logger.file.is_none() && logger.format == Format::Text && logger.verbosity >= 0 {
+        return command.status();
+    }
+
+    let output = command.output()?;
+    let mut lines: Vec<String> = Vec::new();
+    for stream in [&output.stdout, &output.stderr] {
+        lines.extend(String::from_utf8_lossy(stream).lines().map(String::from));
+    }
+    for line in &lines {
+        record(Level::Debug, Some(&program), line);
+    }
+    if !output.status.success() && !enabled(Level::Debug) {
+        for line in &lines[lines.len().saturating_sub(FAILURE_TAIL)..] {
+            terminal(Level::Error, Some(&program), line);
+        }
+    }
+    Ok(output.status)
+}
@@ -0,0 +1,464 @@
+
+use clap::{arg, ArgAction, Command, value_parser};
+use colored::*;
+use std::path::PathBuf;
+use std::process;
+
+use machinegen::{render, skeleton, tables, types, user_config, workspace};
+use types::MachinegenError;
+use workspace::Workspace;
+
+mod util;
+mod build;
+mod check;
+mod clean;
+mod list;
+mod pull;
+mod deploy;
+mod destroy;
+mod schema;
+mod validate;
+mod debug;
+mod log;
+
+
+fn run(cli: clap::ArgMatches) -> Result<(), MachinegenError> {
+    let verbosity = cli.get_one::<u8>("verbose").copied().unwrap_or_default() as i8
+        - cli.get_one::<u8>("quiet").copied().unwrap_or_default() as i8;
+    let format = cli
+        .get_one::<String>("log-format")
+        .and_then(|name| log::Format::from_name(name))
+        .unwrap_or(log::Format::Text);
+    log::init(
+        verbosity,
+        format,
+        cli.get_one::<PathBuf>("log-file").map(PathBuf::as_path),
+    )?;
+
+    let mut workspace =
+        Workspace::resolve(cli.get_one::<PathBuf>("workspace").map(PathBuf::as_path))?;
+    if let Some(name) = cli.get_one::<String>("machine-name") {
+        workspace = workspace.with_machine(name)?;
+    }
+    log::debug(&format!(
+        "Working on machine `{}` of the workspace in {}",
+        workspace.machine(),
+        workspace.dir().display()
+    ));
+    util::set_workspace(workspace);
+
+    match cli.subcommand() {
+        Some(("pull", sub_m)) => pull::run(sub_m)?,
+        Some(("build", sub_m)) => build::run(sub_m)?,
+        Some(("deploy", sub_m)) => deploy::run(sub_m)?,
+        Some(("destroy", sub_m)) => destroy::run(sub_m)?,
+        Some(("clean", sub_m)) => clean::run(sub_m)?,
+        Some(("schema", sub_m)) => schema::run(sub_m)?,
+        Some(("validate", sub_m)) => validate::run(sub_m)?,
+        Some(("check", sub_m)) => check::run(sub_m)?,
+        Some(("list", sub_m)) => list::run(sub_m)?,
+        Some(("debug", sub_m)) => debug::run(sub_m)?,
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn main() {
+    let matches = Command::new("machinegen")
+        .version("0.1.0")
+        .author("Agata Ordano - aordano@protonmail.com")
+        .about("Utility that prepares, builds and deploys a KVM virtual machine with a certain configuration.")
+        .long_about(concat! ("This utility helps with the steps needed to deploy a custom machine under KVM.\n", 
+        "It can be used to prepare a machine, build it and deploy it to a KVM host using Terraform.\n\n",
+        "Using this can be thought of as in three different parts; the fetching, the processing and the deployment.\n", 
+        "The fetching part lets you gather all the runtime dependencies, templates, and configuration from a remote source. ",
+        "As this is intended to be used in the host where the machines will be deployed, is important to have a way of gathering ", 
+        "all the required stuff from elsewhere.\n", "The processing part uses some of the dependencies, templates, and configuration to build a", 
+        "image and metadata that will let you construct a Terraform plan compatible with your needs.\n", "The deployment part is the last step ", 
+        "in the process, where this tool will ease the process of invoking Terraform with the right parameters, and launching the guest.\n\n",
+        "Exit codes: 3 for machine config table errors, 4 for config errors, 5 for user config validation errors, 6 for I/O errors, ",
+        "7 for failed external programs (git, terraform), 8 for failed downloads, 9 when the workspace is not ready for the command, ",
+        "10 when the seed image can't be built and 11 when machinegen can't write its own JSON output."))
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .arg(
+            arg!(-w --workspace <DIR> "Folder holding the .machinegen folder to work in.")
+            .required(false)
+            .global(true)
+            .value_parser(value_parser!(PathBuf))
+            .long_help(concat!("Folder holding the .machinegen folder to work in. Without it, the folder in the MACHINEGEN_HOME ",
+            "environment variable is used, and without that, the closest folder holding a .machinegen folder going up from the ",
+            "current one, like git does for .git. If there is none, the current folder is used.")))
+        .arg(
+            arg!(-v --verbose "Shows more of what is going on, debug records included.")
+            .global(true)
+            .action(ArgAction::Count)
+            .long_help(concat!("Shows more of what is going on. Once shows debug records, along with the output of the ",
+            "programs run (git, terraform) when it's captured.")))
+        .arg(
+            arg!(-q --quiet "Shows less of what is going on.")
+            .global(true)
+            .action(ArgAction::Count)
+            .conflicts_with("verbose")
+            .long_help(concat!("Shows less of what is going on. Once leaves only warnings and errors, twice only errors. ",
+            "The output of the programs run (git, terraform) is captured, and only shown when they fail.")))
+        .arg(
+            arg!(--"log-format" <FORMAT> "Format of the log records.")
+            .required(false)
+            .global(true)
+            .value_parser(["text", "json"])
+            .default_value("text")
+            .long_help(concat!("Format of the log records. With json, every record is a JSON object on its own line, holding ",
+            "its timestamp, level and message, and the source program for captured output. Errors and warnings go to stderr, ",
+            "everything else to stdout.")))
+        .arg(
+            arg!(--"log-file" <PATH> "Appends every log record to a file.")
+            .required(false)
+            .global(true)
+            .value_parser(value_parser!(PathBuf))
+            .long_help(concat!("Appends every log record to a file, whatever the verbosity, in the log format. The output of ",
+            "the programs run (git, terraform) is captured into it in full, keeping the terminal concise.")))
+        .arg(
+            arg!(--"machine-name" <NAME> "Machine of the workspace to work on.")
+            .required(false)
+            .global(true)
+            .value_parser(value_parser!(String))
+            .long_help(concat!("Machine of the workspace to work on. Every machine has its own user config, build and deployment, ",
+            "kept in .machinegen/machines/<NAME>, while the machine config and the dependencies are shared by all of them.\n",
+            "Without it, the default machine is used, which keeps its user config in .machinegen/config and its build and ",
+            "deployment right in .machinegen. Names are made of ASCII letters, digits, - and _.")))
+        .subcommand(
+            Command::new("pull")
+                .alias("fetch")
+            .about("Manages fetching dependencies, templates and other files from the internet.")
+            .long_about(util::string_to_sstr(format!("{}\n {}", "This command gathers needed files from remote resources.".yellow(), concat!("Configuration data can be categorized ",
+            "in two separate groups; the user-provided config data (a single file with specific details to include in the machine deployment ",
+            "process), and the machine config data (a folder containing lower-level confguration that defines how the user config will be digested ", 
+            "and processed, and specific information about how to perform the deployment).\n", "You can pull either or both of those. ",
+            "Check the help on the arguments for more information."))))
+            .arg_required_else_help(true)
+            .subcommand_required(true)
+            .subcommand(Command::new("deps")
+                .alias("dependencies")
+                .about("Fetches dependencies from the internet.")
+                .long_about(concat!("This subcommand fetches dependencies from the internet.\n", "Dependencies can be categorized ",
+                "in two separate groups; the runtime dependencies (needed for the subsequent steps to function), and the machine image ", 
+                "(needed to build the virtual machine using cloud-init).\n", "You can pull either or both of those. Check the help on the ",
+                "arguments for more information."))
+                .arg_required_else_help(true)
+                .arg(
+                    arg!(-f --force "Force the download of dependencies, even if they are already present.")
+                        .long_help(concat! ("This will pull dependencies again even if a copy that matches its checksum is already present.\n", 
+                        "It will overwrite any existing file with the same name")))
+                .arg(
+                    arg!(-a --all "Downloads all dependencies.")
+                    .conflicts_with_all(&["image", "image-url", "runtime"]))
+                .arg(
+                    arg!(-i --image [NAME] "Downloads machine image.")
+                    .conflicts_with_all(&["all", "image-url", "runtime"])
+                    .multiple_values(false)
+                    .value_parser(value_parser!(String))
+                    .long_help(concat! ("This will pull a machine image from the images table of the machine config.\n", 
+                    "Each image in the table has a name, distro, release, arch, url, sha256 and format (qcow2, raw or img). ",
+                    "Provide the name of the image to pull; without a name, the first image in the table is pulled.\n",
+                    "Images are stored in .machinegen/deps/images/<name>.<format> with their checksum recorded next to them. ",
+                    "Cloud images are usually a ~600MiB download.")))
+                .arg(
+                    arg!(--"image-url" <URI> "Downloads a machine image from an arbitrary URI.")
+                    .required(false)
+                    .conflicts_with_all(&["all", "image", "runtime"])
+                    .multiple_values(false)
+                    .value_parser(value_parser!(String))
+                    .long_help(concat! ("This will pull a machine image that is not listed in the images table, like a golden image of your own.\n", 
+                    "The image name and format are taken from the file name in the URI, and its checksum is recorded after the download.")))
+                .arg(
+                    arg!(-r --runtime "Downloads all runtime dependencies.")
+                    .conflicts_with_all(&["all", "image", "image-url"])
+                    .long_help(concat! ("This will pull the runtime dependencies.\n", 
+                    "Those include the Terraform binary, cloud-init tools, and some other minor stuff.\n",
+                    "The list of dependencies is read from the dependencies table of the machine config ",
+                    "(name, version, url, sha256, target, executable). Each one is downloaded into .machinegen/deps/<target> ",
+                    "and only installed if its SHA-256 checksum matches the table.")))
+                )
+            .subcommand(Command::new("config")
+                .alias("configuration")
+                .about("This command pulls user or machine config file from a remote host.")
+                .long_about(concat!("This subcommand fetches configuration data from the internet.\n", "Configuration data can be categorized ",
+                "in two separate groups; the user-provided config data (a single file with specific details to include in the machine deployment ",
+                "process), and the machine config data (a folder containing lower-level confguration that defines how the user config will be digested ", 
+                "and processed, and specific information about how to perform the deployment).\n", "You can pull either or both of those. ",
+                "Check the help on the arguments for more information."))
+                .arg_required_else_help(true)
+                .arg(
+                    arg!(-f --force "Force the download of config data, even if it is already present.")
+                        .long_help(concat! ("This will download the data again from scratch (in case of user config) ",
+                        "or will reset and clean the git working tree before pulling (in case of machine config).\n", 
+                        "It will overwrite any existing file with the same name")))
+                .arg(
+                    arg!(-u --user "Pulls a user config file from a remote source.")
+                        .long_help(concat!("Provide a URI from where to fetch the config file. Config files can be JSON, JSONC and JSON5.\n", 
+                        "If you don't have a config file, you can generate a commentated skeleton with schema using the --skeleton flag"))
+                        .conflicts_with("machine")
+                        .takes_value(true)
+                        .multiple_values(false)
+                        .value_parser(value_parser!(String)))   
+
+                .arg(
+                    arg!(-s --skeleton [FORMAT] "Prints a commented user config skeleton for the current machine config.")
+                        .long_help(concat!("Builds a user config skeleton out of the pulled machine config tables and prints it to stdout. ",
+                        "Every key comes with its description as a comment, mandatory keys are marked as such, keys that accept ",
+                        "several values are laid out as lists and every expected file is listed in its files group.\n",
+                        "The skeleton can be written as JSONC (the default) or JSON5."))
+                        .conflicts_with_all(&["user", "machine", "force"])
+                        .takes_value(true)
+                        .min_values(0)
+                        .multiple_values(false)
+                        .default_missing_value("jsonc")
+                        .value_parser(["jsonc", "json5"]))
+                .arg(
+                    arg!(-m --machine "Pulls a machine config folder from a remote source.")
+                        .long_help(concat!("Provide the URL of a git repository to clone the config folder from, it will be checked out in .machinegen/config. Machine config is defined as a set of ",
+                        "tables that contain information about how to process templates, and the template files (both for building the cloud-init)",
+                        "image and the required Terraform project.\n", 
+                        "This is not intended to be managed by an user. By default, it will pull the default machine config, from the source of ", 
+                        "this program.\n", "You can learn more at https://github.com/nodoambiental/machinegen/tree/master/config"))
+                        .default_missing_value("https://github.com/nodoambiental/machinegen-config")
+                        .conflicts_with("user")
+                        .takes_value(true)
+                        .min_values(0)
+                        .multiple_values(false)
+                        .value_parser(value_parser!(String)))
+                .arg(
+                    arg!(--ref <REF> "Branch, tag or commit of the machine config to check out.")
+                        .long_help(concat!("Provide a branch, tag or commit hash of the machine config repository to check out. ",
+                        "Branches are fast-forwarded on every pull, tags and commits are checked out as they are. ",
+                        "By default, the default branch of the remote is used.\n",
+                        "The resolved commit hash is recorded in .machinegen/config.lock"))
+                        .required(false)
+                        .requires("machine")
+                        .multiple_values(false)
+                        .value_parser(value_parser!(String)))
+                )
+            )
+        .subcommand(
+            Command::new("build")
+                .alias("process")
+                .about("Performs the processing of the user config and machine config into a ready-to deploy Terraform project.")
+                .long_about(concat!("This subcommand uses the previously fetched configuration data to build both a cloud-init image customized ", 
+                "according to the configuration and a Terraform project that defines a KVM virtual machine that includes that image and further configuration.\n",
+                "The user config is validated first, then every template listed in the templates table is rendered: each {{ key }} placeholder is ",
+                "replaced with the value at config_parent.key in the user config. Keys of groups are named by that dotted path, like {{ nics.primary.mac }}, ",
+                "or by their bare name when no other key of the template shares it. Lines holding a placeholder of a key that takes a list are repeated ",
+                "once per item. Guest templates are written into .machinegen/build/cloud-init and host templates into .machinegen/build/terraform."))
+                .arg_required_else_help(true)
+                .arg(
+                    arg!(-f --force "Force building the machine configuration, even if files are already present.")
+                    .long_help("This will clean every generated file before reattempting the build process.")
+                )
+                .arg(
+                    arg!(-c --cloud "Builds the cloud-init image with the specified configuration.")
+                        .conflicts_with_all(&["terraform", "all"])
+                        .long_help(concat!("This will use the previously fetched machine and user-provided config to build the cloud-init image.\n",
+                        "Guest templates rendering into user-data, meta-data and network-config are packed into a NoCloud seed image labeled cidata, ",
+                        "at .machinegen/build/seed.iso. The image is not built again unless those files change or --force is given."))
+                )
+                .arg(
+                    arg!(-t --terraform "Builds and plans the Terraform project with the specified configuration.")
+                        .conflicts_with_all(&["cloud", "all"])
+                        .long_help(concat! ("This will use the previously fetched machine, user-provided config and cloud-init image to build ", 
+                        "the terraform project, initialize it, and plan it to be ready to deploy.\n",
+                        "Host templates are rendered into .machinegen/build/terraform, along with a machinegen.tf file declaring the ",
+                        "machinegen_seed_iso and machinegen_base_image variables. The plan is saved as machinegen.tfplan in the project."))
+                )
+                .arg(
+                    arg!(--image <NAME> "Base machine image for the Terraform project.")
+                        .long_help(concat!("Name of a pulled machine image to use as the base image of the Terraform project. ",
+                        "By default, the first image of the images table is used."))
+                        .required(false)
+                        .conflicts_with("cloud")
+                        .multiple_values(false)
+                        .value_parser(value_parser!(String))
+                )
+                .arg(
+                    arg!(-a --all "Builds both the cloud init image and the Terraform project.")
+                        .conflicts_with_all(&["cloud", "terraform"])
+                        .long_help(concat! ("This will use the previously fetched machine and user-provided config to do everything needed to ", 
+                        "have the Terraform project ready to deploy."))
+                )
+
+        )
+        .subcommand(
+            Command::new("deploy")
+                .about("This subcommand deploys a previously processed Terraform project.")
+                .long_about(
+                    util::string_to_sstr(format!("This takes a successfully built Terraform project and uses {} to deploy it.\n{}", "terraform apply".italic().green(),
+                    concat!("Only the plan saved by build --terraform is applied, and only while the project, seed image and base image it was made from are unchanged. ",
+                    "The resources it will create, change or destroy are shown before asking for confirmation.\n",
+                    "Outputs of the deployment are recorded in .machinegen/state/deployment.json"))))
+                .arg_required_else_help(false)
+                .arg(
+                    arg!(-y --yes "Deploy without asking for confirmation.")
+                        .long_help("This will apply the plan right after showing its summary. Required when there is no terminal to ask on.")
+                )
+        )
+        .subcommand(
+            Command::new("destroy")
+                .about("This subcommand tears down a deployed machine.")
+                .long_about(util::string_to_sstr(format!("This takes the deployed Terraform project and uses {} to remove every resource it tracks.\n{}",
+                "terraform destroy".italic().green(),
+                concat!("The resources to be removed are shown before asking for confirmation. Resources removed outside of Terraform, ",
+                "like a libvirt domain undefined by hand, are reported as drift.\n",
+                "The deployment record in .machinegen/state/deployment.json is updated afterwards."))))
+                .arg_required_else_help(false)
+                .arg(
+                    arg!(-y --yes "Destroy without asking for confirmation.")
+                        .long_help("This will destroy the resources right after showing them. Required when there is no terminal to ask on.")
+                )
+        )
+        .subcommand(
+            Command::new("clean")
+                .about("This subcommand removes pulled and/or generated files.")
+                .long_about(concat!("This removes the files machinegen keeps under .machinegen, by category; pulled config files, pulled ",
+                "dependencies and generated files. Categories can be combined, and narrowed down with their discriminating flags.\n",
+                "Generated files include the Terraform state, which is kept while it still tracks deployed resources unless --force is given."))
+                .arg_required_else_help(true)
+                .arg(
+                    arg!(-c --config "Clean pulled config files.")
+                    .long_help("This will clean all config files. To discriminate between user or machine config, use --user or --machine flags.")
+                    .takes_value(false)
+                )
+                .arg(
+                    arg!(-u --user "Clean pulled user-provided config files.")
+                    .requires("config")
+                    .takes_value(false)
+                )
+                .arg(
+                    arg!(-m --machine "Clean pulled machine config files.")
+                    .requires("config")
+                    .takes_value(false)
+                )
+                .arg(
+                    arg!(-d --deps "Clean pulled dependencies.")
+                    .long_help("This will clean all dependencies. To discriminate between runtime or image dependencies, use --runtime or --image flags.")
+                    .takes_value(false)
+                )
+                .arg(
+                    arg!(-i --image "Clean pulled image files.")
+                    .requires("deps")
+                    .takes_value(false)
+                )
+                .arg(
+                    arg!(-r --runtime "Clean pulled runtime dependencies.")
+                    .requires("deps")
+                    .takes_value(false)
+                )
+                .arg(
+                    arg!(-g --generated "Clean generated files.")
+                    .long_help(concat!("This will clean every generated file in the build process, along with the deployment record. ",
+                    "It's refused while the Terraform state still tracks deployed resources, unless --force is given."))
+                    .takes_value(false)
+                )
+                .arg(
+                    arg!(-a --all "Clean everything; config files, dependencies and generated files.")
+                    .conflicts_with_all(&["config", "deps", "generated"])
+                    .takes_value(false)
+                )
+                .arg(
+                    arg!(-n --"dry-run" "List what would be removed, without removing anything.")
+                    .long_help("This will list every file or folder that would be removed, along with the space cleaning would free.")
+                    .takes_value(false)
+                )
+                .arg(
+                    arg!(-f --force "Remove the Terraform state even if it still tracks deployed resources.")
+                    .long_help(concat!("Generated files are kept while the Terraform state still tracks deployed resources, as Terraform ",
+                    "would lose track of them. This removes them anyway."))
+                    .takes_value(false)
+                )
+        )
+        .subcommand(
+            Command::new("schema")
+                .about("Builds a JSON Schema for the user config out of the machine config tables.")
+                .long_about(concat!("This takes the pulled machine config tables and builds a JSON Schema (draft 2020-12) document ",
+                "describing the user config they expect, so editors can validate and autocomplete user config files.\n",
+                "The schema is written next to the tables, as .machinegen/config/tables/user.schema.json"))
+                .arg(
+                    arg!(--id <URI> "The $id of the schema.")
+                    .long_help("URI to use as the $id of the schema. By default it's the file URI of the written schema.")
+                    .required(false)
+                    .multiple_values(false)
+                    .value_parser(value_parser!(String))
+                )
+                .arg(
+                    arg!(--stdout "Prints the schema to stdout instead of writing it next to the tables.")
+                )
+        )
+        .subcommand(
+            Command::new("validate")
+                .about("Checks the user config against the machine config tables.")
+                .long_about(concat!("This checks the pulled user config against the pulled machine config tables, looking for missing ",
+                "mandatory keys, single values given where a list is expected (or the other way around), keys that are not present in the ",
+                "replace or files tables, and missing entries in files groups.\n",
+                "Every problem is reported along with the dotted path of its key. This check also runs before every build."))
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Checks the references between the machine config tables.")
+                .long_about(concat!("This checks the pulled machine config tables against each other, looking for replacements ",
+                "pointing at templates that don't exist, template sources missing from the machine config, names used more than once ",
+                "in or across tables, files and replace keys colliding in the same group, and templates of a system writing the same target.\n",
+                "Every problem is reported along with the table row it was found at."))
+        )
+        .subcommand(
+            Command::new("list")
+                .alias("ls")
+                .about("Lists the machines of the workspace and their status.")
+                .long_about(concat!("This lists the default machine and every named machine of the workspace, along with ",
+                "the user config it has, how far it has been built and whether it's deployed."))
+        )
+        .subcommand(
+            Command::new("debug")
+                .alias("inspect")
+                .hide(true)
+                .about("Shows the internal representation of the machine config.")
+                .long_about(concat!("This shows how machinegen reads the machine config tables and the user config, ",
+                "for troubleshooting machine configs and machinegen itself. Everything is written to stdout, as pretty JSON ",
+                "by default."))
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .arg(
+                    arg!(--format <FORMAT> "Output format.")
+                    .required(false)
+                    .global(true)
+                    .value_parser(["json", "compact", "debug"])
+                    .default_value("json")
+                    .long_help("Output format: pretty JSON, JSON on a single line, or the Rust debug representation."))
+                .subcommand(Command::new("table")
+                    .about("Shows the parsed rows of a machine config table.")
+                    .arg(
+                        arg!(<TABLE> "Table to show.")
+                        .id("table")
+                        .value_parser(["replace", "files", "templates", "dependencies", "images"])))
+                .subcommand(Command::new("data")
+                    .about("Shows the machine data related out of the replace, files and templates tables."))
+                .subcommand(Command::new("config")
+                    .about("Shows the tree of config keys, with the values the user config gives them.")
+                    .long_about(concat!("This shows the tree of config keys related out of the replace and files tables, ",
+                    "every key with its description, whether it's mandatory and unique, and either its children or the value ",
+                    "the user config gives it. Without a readable user config, values are null.")))
+                .subcommand(Command::new("replacements")
+                    .about("Shows what each template placeholder gets replaced with.")
+                    .long_about(concat!("This shows, for every template, the placeholders it holds, the user config key ",
+                    "each one is taken from, and the value it gets replaced with, or why it can't be. Without a readable ",
+                    "user config, values are null."))
+                    .arg(
+                        arg!([TEMPLATE] "Only show the placeholders of this template.")
+                        .id("template")
+                        .value_parser(value_parser!(String))))
+        )
+        .get_matches();
+        if let Err(error) = run(matches) {
+            log::error(&format!("Application error: {}", error));
+            process::exit(error.exit_code());
+        }
+
+}
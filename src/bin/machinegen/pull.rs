@@ -0,0 +1,383 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+mod download;
+mod git;
+
+use download::install;
+
+use super::types::{
+    Dependency, Image, ImageFormat, MachinegenError, Records, TableTypes, UserConfigFormat,
+};
+use super::{log, skeleton, user_config, util};
+
+pub fn run(sub_match: &clap::ArgMatches) -> Result<(), MachinegenError> {
+    match sub_match.subcommand() {
+        Some(("deps", deps_match)) => deps(deps_match),
+        Some(("config", config_match)) => config(config_match),
+        _ => unreachable!(),
+    }
+}
+
+fn deps(deps_match: &clap::ArgMatches) -> Result<(), MachinegenError> {
+    let force = deps_match.contains_id("force");
+
+    if deps_match.contains_id("runtime") {
+        runtime(force)
+    } else if let Some(url) = deps_match.get_one::<String>("image-url") {
+        image_from_url(url, force)
+    } else if deps_match.contains_id("image") {
+        image(deps_match.get_one::<String>("image"), force)
+    } else if deps_match.contains_id("all") {
+        runtime(force)?;
+        image(None, force)
+    } else {
+        log::warning("Please specify which dependencies to pull. Use --help for more information.");
+        Ok(())
+    }
+}
+
+fn config(config_match: &clap::ArgMatches) -> Result<(), MachinegenError> {
+    let force = config_match.contains_id("force");
+
+    if let Some(format) = config_match.get_one::<String>("skeleton") {
+        user_config_skeleton(format)
+    } else if let Some(url) = config_match.get_one::<String>("machine") {
+        machine_config(
+            url,
+            config_match.get_one::<String>("ref").map(String::as_str),
+            force,
+        )
+    } else if let Some(uri) = config_match.get_one::<String>("user") {
+        user_config(uri, force)
+    } else {
+        log::warning("Please specify which config to pull. Use --help for more information.");
+        Ok(())
+    }
+}
+
+fn user_config_skeleton(format: &str) -> Result<(), MachinegenError> {
+    let format = UserConfigFormat::from_extension(format).ok_or_else(|| {
+        MachinegenError::Config(format!("{} is not a valid skeleton format", format))
+    })?;
+    let data = util::process_relations()?;
+
+    print!("{}", skeleton::generate(&data, format));
+    Ok(())
+}
+
+fn user_config(uri: &str, force: bool) -> Result<(), MachinegenError> {
+    if let Some((path, _)) = util::user_config_path() {
+        if !force {
+            log::info(&format!(
+                "User config is already present at {}, use --force to replace it.",
+                path.display()
+            ));
+            return Ok(());
+        }
+    }
+
+    // Plain paths are taken as local files
+    let url = if uri.contains("://") {
+        uri.to_string()
+    } else {
+        let path =
+            fs::canonicalize(uri).map_err(|error| MachinegenError::io(Path::new(uri), error))?;
+        format!("file://{}", path.display())
+    };
+
+    let folder = util::workspace().user();
+    fs::create_dir_all(&folder).map_err(|error| MachinegenError::io(&folder, error))?;
+    let partial = folder.join("user.part");
+    let _ = fs::remove_file(&partial);
+
+    download::fetch_with_retries("user config", &url, &partial).map_err(|message| {
+        MachinegenError::Download {
+            url: url.clone(),
+            message,
+        }
+    })?;
+
+    let content = fs::read_to_string(&partial);
+    let _ = fs::remove_file(&partial);
+    let content = content.map_err(|error| MachinegenError::io(&partial, error))?;
+
+    // The extension wins if there is one, otherwise the content tells
+    let extension = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|path| Path::new(path).extension())
+        .and_then(|extension| extension.to_str())
+        .and_then(UserConfigFormat::from_extension);
+    let format = match extension {
+        Some(format) => format,
+        None => user_config::detect_format(&content)
+            .map_err(|error| MachinegenError::Config(format!("{}:{}", uri, error)))?,
+    };
+    user_config::parse(&content, format)
+        .map_err(|error| MachinegenError::Config(format!("{}:{}", uri, error)))?;
+
+    // Only one user config at a time, whatever its format
+    for old_format in UserConfigFormat::ALL {
+        let _ = fs::remove_file(folder.join("user").with_extension(old_format.extension()));
+    }
+    let target = folder.join("user").with_extension(format.extension());
+    fs::write(&target, content).map_err(|error| MachinegenError::io(&target, error))?;
+
+    log::success(&format!(
+        "Stored {} user config at {}",
+        format.extension().to_uppercase(),
+        target.display()
+    ));
+    Ok(())
+}
+
+fn machine_config(url: &str, reference: Option<&str>, force: bool) -> Result<(), MachinegenError> {
+    let repository = util::workspace().config();
+
+    // The user config lives in the same folder, cleaning must leave it alone
+    let commit = git::sync(url, &repository, reference, force, &["/user.*"])
+        .map_err(|message| MachinegenError::subprocess("git", &message))?;
+
+    let lock_path = util::workspace().config_lock();
+    util::write_lock(
+        &lock_path,
+        &[
+            ("url", url),
+            ("ref", reference.unwrap_or("HEAD")),
+            ("commit", &commit),
+        ],
+    )
+    .map_err(|error| MachinegenError::io(&lock_path, error))?;
+
+    log::success(&format!(
+        "Machine config is at commit {} from {}",
+        commit, url
+    ));
+    Ok(())
+}
+
+fn image(name: Option<&String>, force: bool) -> Result<(), MachinegenError> {
+    let catalog = load_image_catalog()?;
+    let image = select_image(&catalog, name.map(String::as_str))?;
+
+    log::info(&format!(
+        "Selected image {} ({} {} {}, {})",
+        image.name,
+        image.distro,
+        image.release,
+        image.arch,
+        image.format.value()
+    ));
+
+    install(
+        &image.name,
+        &image.url,
+        Some(&image.sha256),
+        &image_path(&image.name, &image.format)?,
+        force,
+    )
+    .map_err(|message| MachinegenError::Download {
+        url: image.url.clone(),
+        message: format!("could not install image {}: {}", image.name, message),
+    })?;
+    Ok(())
+}
+
+fn image_from_url(url: &str, force: bool) -> Result<(), MachinegenError> {
+    let unnamed = || MachinegenError::Download {
+        url: url.to_string(),
+        message: String::from("can't infer an image name from the URL"),
+    };
+    let file_name = match url.rsplit('/').next() {
+        Some(file_name) if !file_name.is_empty() => file_name,
+        _ => return Err(unnamed()),
+    };
+    let path = Path::new(file_name);
+    let format = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(ImageFormat::from_extension)
+        .unwrap_or(ImageFormat::Img);
+    let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some(name) => name,
+        None => return Err(unnamed()),
+    };
+
+    install(name, url, None, &image_path(name, &format)?, force).map_err(|message| {
+        MachinegenError::Download {
+            url: url.to_string(),
+            message,
+        }
+    })?;
+    Ok(())
+}
+
+/// Picks an image from the catalog by name, the first one being the default.
+fn select_image<'a>(
+    catalog: &'a [Image],
+    name: Option<&str>,
+) -> Result<&'a Image, MachinegenError> {
+    match name {
+        Some(name) => match catalog.iter().find(|image| image.name == name) {
+            Some(image) => Ok(image),
+            None => Err(MachinegenError::Config(format!(
+                "There is no image named {} in the images table. Available images: {}",
+                name,
+                catalog
+                    .iter()
+                    .map(|image| image.name.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            ))),
+        },
+        None => match catalog.first() {
+            Some(image) => Ok(image),
+            None => Err(MachinegenError::Config(String::from(
+                "The images table is empty.",
+            ))),
+        },
+    }
+}
+
+/// Finds an already pulled image, either pulled from the catalog or from an arbitrary URL.
+/// Without a name, the default image of the catalog is used.
+pub fn pulled_image(name: Option<&str>) -> Result<PathBuf, MachinegenError> {
+    if let Some(name) = name {
+        for format in [ImageFormat::Qcow2, ImageFormat::Raw, ImageFormat::Img] {
+            let path = image_path(name, &format)?;
+            if path.is_file() {
+                return Ok(path);
+            }
+        }
+    }
+
+    let catalog = load_image_catalog()?;
+    let image = select_image(&catalog, name)?;
+    let path = image_path(&image.name, &image.format)?;
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(MachinegenError::Precondition(format!(
+            "Image {} has not been pulled yet, use `machinegen pull deps --image {}` first.",
+            image.name, image.name
+        )))
+    }
+}
+
+/// Location of a pulled image inside the images cache. Names come from the images table or
+/// from URLs, like dependency targets they must never lead outside of it.
+pub fn image_path(name: &str, format: &ImageFormat) -> Result<PathBuf, MachinegenError> {
+    let mut components = Path::new(name).components();
+    if !matches!(components.next(), Some(Component::Normal(_)))
+        || components.next().is_some()
+        || name.contains(['/', '\\'])
+    {
+        return Err(MachinegenError::Config(format!(
+            "Image name `{}` must be a plain file name inside the images folder.",
+            name
+        )));
+    }
+
+    let mut path = util::workspace().images().join(name);
+    path.set_extension(format.value());
+    Ok(path)
+}
+
+fn runtime(force: bool) -> Result<(), MachinegenError> {
+    let manifest = load_manifest()?;
+    let deps_path = util::workspace().deps();
+
+    if manifest.is_empty() {
+        log::warning("The dependencies table is empty, there is nothing to pull.");
+        return Ok(());
+    }
+
+    let mut failed: Vec<String> = Vec::new();
+
+    for dependency in &manifest {
+        if let Err(error) = install_dependency(dependency, &deps_path, force) {
+            log::error(&format!(
+                "Could not install {} {}: {}",
+                dependency.name, dependency.version, error
+            ));
+            failed.push(dependency.name.clone());
+        }
+    }
+
+    if failed.is_empty() {
+        log::success(&format!(
+            "All {} runtime dependencies are in place.",
+            manifest.len()
+        ));
+        Ok(())
+    } else {
+        Err(MachinegenError::Download {
+            url: String::from("the dependencies table"),
+            message: format!("failed to pull {}", failed.join(", ")),
+        })
+    }
+}
+
+fn load_manifest() -> Result<Vec<Dependency>, MachinegenError> {
+    let table = util::load_table(TableTypes::Dependencies)?;
+
+    let mut manifest: Vec<Dependency> = Vec::new();
+    for record in table {
+        match record {
+            Records::Dependency(dependency) => manifest.push(dependency),
+            _ => return Err(util::mislabeled(TableTypes::Dependencies)),
+        }
+    }
+    Ok(manifest)
+}
+
+fn load_image_catalog() -> Result<Vec<Image>, MachinegenError> {
+    let table = util::load_table(TableTypes::Images)?;
+
+    let mut catalog: Vec<Image> = Vec::new();
+    for record in table {
+        match record {
+            Records::Image(image) => catalog.push(image),
+            _ => return Err(util::mislabeled(TableTypes::Images)),
+        }
+    }
+    Ok(catalog)
+}
+
+fn install_dependency(
+    dependency: &Dependency,
+    deps_path: &Path,
+    force: bool,
+) -> Result<(), String> {
+    // Targets are relative to the deps folder, never allow them to escape it
+    if dependency
+        .target
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return Err(format!(
+            "target {} must be a relative path inside the deps folder",
+            dependency.target.display()
+        ));
+    }
+
+    let target = deps_path.join(&dependency.target);
+
+    install(
+        &format!("{} {}", dependency.name, dependency.version),
+        &dependency.url,
+        Some(&dependency.sha256),
+        &target,
+        force,
+    )?;
+
+    #[cfg(unix)]
+    if dependency.executable {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o755))
+            .map_err(|error| error.to_string())?;
+    }
+
+    Ok(())
+}
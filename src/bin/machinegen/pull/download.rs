@@ -0,0 +1,308 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{log, util};
+
+const ATTEMPTS: u32 = 5;
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+struct FetchError {
+    message: String,
+    retryable: bool,
+}
+
+impl FetchError {
+    fn retryable(message: String) -> FetchError {
+        FetchError {
+            message,
+            retryable: true,
+        }
+    }
+
+    fn fatal(message: String) -> FetchError {
+        FetchError {
+            message,
+            retryable: false,
+        }
+    }
+}
+
+/// Downloads `url` into `target`, verifying it against `sha256` when given.
+/// The checksum of the installed file is recorded next to it in a `.sha256` file,
+/// which is also what an existing copy is verified against when no checksum is given.
+///
+/// The download goes into a `.part` file that survives failed attempts, so an interrupted
+/// download resumes where it stopped. It's only moved into `target` once verified.
+pub fn install(
+    label: &str,
+    url: &str,
+    sha256: Option<&str>,
+    target: &Path,
+    force: bool,
+) -> Result<(), String> {
+    let checksum_path = sidecar_path(target, "sha256");
+    let expected = match sha256 {
+        Some(sha256) => Some(sha256.to_string()),
+        None => fs::read_to_string(&checksum_path)
+            .ok()
+            .and_then(|content| content.split_whitespace().next().map(String::from)),
+    };
+
+    if !force && target.exists() {
+        match (util::sha256_file(target), &expected) {
+            (Ok(checksum), Some(expected)) if checksum.eq_ignore_ascii_case(expected) => {
+                log::info(&format!(
+                    "{} is already present and verified, skipping.",
+                    label
+                ));
+                return Ok(());
+            }
+            _ => log::warning(&format!(
+                "{} is present but can't be verified, pulling it again.",
+                target.display()
+            )),
+        }
+    }
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|error| error.to_string())?;
+    }
+
+    let partial = sidecar_path(target, "part");
+    if force && partial.exists() {
+        fs::remove_file(&partial).map_err(|error| error.to_string())?;
+    }
+
+    log::info(&format!("Pulling {} from {}", label, url));
+
+    fetch_with_retries(label, url, &partial)?;
+
+    let checksum = util::sha256_file(&partial).map_err(|error| error.to_string())?;
+    if let Some(sha256) = sha256 {
+        if !checksum.eq_ignore_ascii_case(sha256) {
+            let _ = fs::remove_file(&partial);
+            return Err(format!(
+                "checksum mismatch, expected {} but got {}. The file was not installed",
+                sha256, checksum
+            ));
+        }
+    }
+
+    fs::rename(&partial, target).map_err(|error| error.to_string())?;
+    fs::write(
+        &checksum_path,
+        format!(
+            "{}  {}\n",
+            checksum,
+            target
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default()
+        ),
+    )
+    .map_err(|error| error.to_string())?;
+
+    log::success(&format!("Installed {} at {}", label, target.display()));
+    Ok(())
+}
+
+/// `path` with `extension` appended to its full file name, e.g. `image.qcow2.sha256`.
+pub fn sidecar_path(path: &Path, extension: &str) -> PathBuf {
+    let mut sidecar = path.to_path_buf().into_os_string();
+    sidecar.push(".");
+    sidecar.push(extension);
+    PathBuf::from(sidecar)
+}
+
+/// Fetches `url` into `destination`, retrying with exponential backoff.
+/// Whatever was already written to `destination` is kept between attempts and resumed from.
+pub fn fetch_with_retries(label: &str, url: &str, destination: &Path) -> Result<(), String> {
+    let mut backoff = FIRST_BACKOFF;
+
+    for attempt in 1..=ATTEMPTS {
+        match fetch(label, url, destination) {
+            Ok(()) => return Ok(()),
+            Err(error) if !error.retryable || attempt == ATTEMPTS => {
+                return Err(error.message);
+            }
+            Err(error) => {
+                log::warning(&format!(
+                    "Attempt {}/{} to pull {} failed: {}. Retrying in {}s.",
+                    attempt,
+                    ATTEMPTS,
+                    label,
+                    error.message,
+                    backoff.as_secs()
+                ));
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+        }
+    }
+    unreachable!("The last attempt always returns.");
+}
+
+fn fetch(label: &str, url: &str, destination: &Path) -> Result<(), FetchError> {
+    if let Some(path) = url.strip_prefix("file://") {
+        fetch_file(label, Path::new(path), destination)
+    } else if url.starts_with("http://") || url.starts_with("https://") {
+        fetch_http(label, url, destination)
+    } else {
+        Err(FetchError::fatal(format!(
+            "unsupported URL scheme in {}",
+            url
+        )))
+    }
+}
+
+fn fetch_file(label: &str, source: &Path, destination: &Path) -> Result<(), FetchError> {
+    let mut file = fs::File::open(source)
+        .map_err(|error| FetchError::fatal(format!("{}: {}", source.display(), error)))?;
+    let total = file.metadata().ok().map(|metadata| metadata.len());
+    let mut output = fs::File::create(destination)
+        .map_err(|error| FetchError::fatal(format!("{}: {}", destination.display(), error)))?;
+
+    copy_with_progress(label, &mut file, &mut output, 0, total)
+}
+
+fn fetch_http(label: &str, url: &str, destination: &Path) -> Result<(), FetchError> {
+    let offset = fs::metadata(destination)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(30))
+        .timeout_read(Duration::from_secs(60))
+        .build();
+    let mut request = agent.get(url);
+    if offset > 0 {
+        request = request.set("Range", &format!("bytes={}-", offset));
+    }
+
+    let response = match request.call() {
+        Ok(response) => response,
+        // The partial file already holds the whole resource, verification will tell if it's right
+        Err(ureq::Error::Status(416, _)) if offset > 0 => return Ok(()),
+        Err(ureq::Error::Status(code, response)) => {
+            let message = format!("{} answered {} {}", url, code, response.status_text());
+            return Err(if code >= 500 || code == 408 || code == 429 {
+                FetchError::retryable(message)
+            } else {
+                FetchError::fatal(message)
+            });
+        }
+        Err(error) => return Err(FetchError::retryable(error.to_string())),
+    };
+
+    let length = response
+        .header("Content-Length")
+        .and_then(|length| length.parse::<u64>().ok());
+
+    // Servers that ignore the range send the whole resource again
+    let (mut output, offset) = if response.status() == 206 {
+        let output = OpenOptions::new()
+            .append(true)
+            .open(destination)
+            .map_err(|error| FetchError::fatal(format!("{}: {}", destination.display(), error)))?;
+        log::info(&format!(
+            "Resuming {} from {}.",
+            label,
+            util::human_size(offset)
+        ));
+        (output, offset)
+    } else {
+        let output = fs::File::create(destination)
+            .map_err(|error| FetchError::fatal(format!("{}: {}", destination.display(), error)))?;
+        (output, 0)
+    };
+
+    let total = length.map(|length| length + offset);
+    copy_with_progress(
+        label,
+        &mut response.into_reader(),
+        &mut output,
+        offset,
+        total,
+    )?;
+
+    match total {
+        Some(total) => {
+            let written = fs::metadata(destination)
+                .map(|metadata| metadata.len())
+                .unwrap_or(0);
+            if written < total {
+                Err(FetchError::retryable(format!(
+                    "connection closed after {} of {}",
+                    util::human_size(written),
+                    util::human_size(total)
+                )))
+            } else {
+                Ok(())
+            }
+        }
+        None => Ok(()),
+    }
+}
+
+fn copy_with_progress(
+    label: &str,
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    offset: u64,
+    total: Option<u64>,
+) -> Result<(), FetchError> {
+    let mut buffer = [0u8; 64 * 1024];
+    let mut done = offset;
+    let mut last_report = Instant::now();
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => {
+                util::progress_done();
+                return Err(FetchError::retryable(error.to_string()));
+            }
+        };
+        if let Err(error) = writer.write_all(&buffer[..read]) {
+            util::progress_done();
+            return Err(FetchError::fatal(error.to_string()));
+        }
+        done += read as u64;
+
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            util::progress(label, &progress_message(done, total));
+            last_report = Instant::now();
+        }
+    }
+
+    util::progress(label, &progress_message(done, total));
+    util::progress_done();
+    writer
+        .flush()
+        .map_err(|error| FetchError::fatal(error.to_string()))
+}
+
+fn progress_message(done: u64, total: Option<u64>) -> String {
+    const WIDTH: u64 = 30;
+
+    match total {
+        Some(total) if total > 0 => {
+            let filled = (done.min(total) * WIDTH / total) as usize;
+            format!(
+                "[{}{}] {:>3}% {}/{}",
+                "#".repeat(filled),
+                " ".repeat(WIDTH as usize - filled),
+                done.min(total) * 100 / total,
+                util::human_size(done),
+                util::human_size(total)
+            )
+        }
+        _ => util::human_size(done),
+    }
+}
@@ -0,0 +1,211 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use super::log;
+
+/// Runs git inside `repository`, its output handled by `log::run`.
+fn git(repository: &Path, args: &[&str], error_message: &str) -> Result<(), String> {
+    let status = log::run(Command::new("git").arg("-C").arg(repository).args(args));
+
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(_) => Err(String::from(error_message)),
+        Err(error) => Err(format!("Could not run git: {}", error)),
+    }
+}
+
+/// Runs git inside `repository` and returns its trimmed stdout, or `None` if git failed.
+fn git_output(repository: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repository)
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+/// Where a checkout ends up pointing at.
+enum Target {
+    Branch(String),
+    Detached(String),
+}
+
+/// Clones `url` into `repository`, or fetches it if there's a repository already there,
+/// and checks out `reference` (a branch, tag or commit; the remote default branch if `None`).
+/// Branches are fast-forwarded, never reset, unless `force` is set, in which case the
+/// working tree is cleaned first and the branch reset to the remote one, local commits
+/// included. Files matching `keep` survive the cleaning.
+///
+/// Returns the hash of the commit that ends up checked out.
+pub fn sync(
+    url: &str,
+    repository: &Path,
+    reference: Option<&str>,
+    force: bool,
+    keep: &[&str],
+) -> Result<String, String> {
+    std::fs::create_dir_all(repository).map_err(|error| error.to_string())?;
+
+    if repository.join(".git").exists() {
+        git(
+            repository,
+            &["remote", "set-url", "origin", url],
+            "Could not point the machine config repository to the new URL.",
+        )?;
+    } else {
+        // Not a plain clone, as the folder may already hold the user config
+        git(
+            repository,
+            &["init", "--quiet"],
+            "Could not initialize the machine config repository.",
+        )?;
+        git(
+            repository,
+            &["remote", "add", "origin", url],
+            "Could not add the machine config remote.",
+        )?;
+    }
+
+    log::info(&format!("Fetching machine config from {}", url));
+    git(
+        repository,
+        &[
+            "fetch",
+            "--quiet",
+            "--tags",
+            "--force",
+            "--prune",
+            "origin",
+            "+refs/heads/*:refs/remotes/origin/*",
+        ],
+        &format!("Could not fetch {}", url),
+    )?;
+
+    if force {
+        log::warning("Cleaning the machine config working tree before checking it out.");
+        let mut clean_args = vec!["clean", "-ffdx", "--quiet"];
+        for pattern in keep {
+            clean_args.push("-e");
+            clean_args.push(pattern);
+        }
+        if git_output(repository, &["rev-parse", "--verify", "HEAD"]).is_some() {
+            git(
+                repository,
+                &["reset", "--hard", "--quiet"],
+                "Could not reset the machine config working tree.",
+            )?;
+        }
+        git(
+            repository,
+            &clean_args,
+            "Could not clean the machine config working tree.",
+        )?;
+    }
+
+    let target = resolve(repository, reference)?;
+
+    match target {
+        Target::Branch(branch) => {
+            let local = format!("refs/heads/{}", branch);
+            let remote = format!("origin/{}", branch);
+            if force {
+                // Local commits are discarded along with the rest of the local changes
+                git(
+                    repository,
+                    &["checkout", "--quiet", "-B", &branch, "--track", &remote],
+                    &format!("Could not reset branch {} to {}.", branch, remote),
+                )?;
+            } else if git_output(repository, &["rev-parse", "--verify", "--quiet", &local])
+                .is_some()
+            {
+                git(
+                    repository,
+                    &["checkout", "--quiet", &branch],
+                    &format!("Could not check out branch {}.", branch),
+                )?;
+                git(
+                    repository,
+                    &["merge", "--ff-only", "--quiet", &remote],
+                    &format!(
+                        "Branch {} can't be fast-forwarded to {}. Use --force to discard local changes.",
+                        branch, remote
+                    ),
+                )?;
+            } else {
+                git(
+                    repository,
+                    &["checkout", "--quiet", "-b", &branch, "--track", &remote],
+                    &format!("Could not check out branch {}.", branch),
+                )?;
+            }
+        }
+        Target::Detached(commit) => {
+            git(
+                repository,
+                &["checkout", "--quiet", "--detach", &commit],
+                &format!("Could not check out {}.", commit),
+            )?;
+        }
+    }
+
+    match git_output(repository, &["rev-parse", "HEAD"]) {
+        Some(commit) => Ok(commit),
+        None => Err(String::from(
+            "Could not resolve the checked out machine config commit.",
+        )),
+    }
+}
+
+fn resolve(repository: &Path, reference: Option<&str>) -> Result<Target, String> {
+    let reference = match reference {
+        Some(reference) => reference.to_string(),
+        None => {
+            // Ask the remote which one is its default branch
+            let symref = git_output(repository, &["ls-remote", "--symref", "origin", "HEAD"])
+                .unwrap_or_default();
+            match symref
+                .lines()
+                .find_map(|line| line.strip_prefix("ref: refs/heads/"))
+                .and_then(|line| line.split_whitespace().next())
+            {
+                Some(branch) => return Ok(Target::Branch(branch.to_string())),
+                None => return Err(String::from(
+                    "Could not find out the default branch of the machine config remote. Use --ref to pick one.",
+                )),
+            }
+        }
+    };
+
+    let remote_branch = format!("refs/remotes/origin/{}", reference);
+    if git_output(
+        repository,
+        &["rev-parse", "--verify", "--quiet", &remote_branch],
+    )
+    .is_some()
+    {
+        return Ok(Target::Branch(reference));
+    }
+
+    match git_output(
+        repository,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{}^{{commit}}", reference),
+        ],
+    ) {
+        Some(commit) => Ok(Target::Detached(commit)),
+        None => Err(format!(
+            "{} is not a branch, tag or commit of the machine config remote.",
+            reference
+        )),
+    }
+}
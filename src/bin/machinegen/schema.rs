@@ -0,0 +1,27 @@
+use machinegen::schema;
+use std::fs;
+
+use super::types::MachinegenError;
+use super::{log, util};
+
+pub fn run(sub_match: &clap::ArgMatches) -> Result<(), MachinegenError> {
+    let data = util::process_relations()?;
+
+    let path = util::workspace().tables().join("user.schema.json");
+    let id = match sub_match.get_one::<String>("id") {
+        Some(id) => id.clone(),
+        None => format!("file://{}", path.display()),
+    };
+
+    let schema = schema::generate(&data, &id);
+    let content = serde_json::to_string_pretty(&schema).map_err(MachinegenError::Serialization)?;
+
+    if sub_match.contains_id("stdout") {
+        println!("{}", content);
+        return Ok(());
+    }
+
+    fs::write(&path, content + "\n").map_err(|error| MachinegenError::io(&path, error))?;
+    log::success(&format!("User config schema written to {}", path.display()));
+    Ok(())
+}
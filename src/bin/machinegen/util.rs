@@ -0,0 +1,186 @@
+use colored::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::{env, fs, io, process};
+
+use super::types::{MachineData, MachinegenError, TableTypes, Tables, UserConfigFormat};
+use super::workspace::Workspace;
+use super::{log, tables, user_config};
+
+static PROGRESS_DRAWN: AtomicBool = AtomicBool::new(false);
+static WORKSPACE: OnceLock<Workspace> = OnceLock::new();
+
+// From https://stackoverflow.com/a/52367953/16134348
+pub fn string_to_sstr(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+}
+
+/// Redraws a single progress line in place. Nothing is printed unless stdout is a terminal showing text logs.
+pub fn progress(label: &str, message: &str) {
+    if !io::stdout().is_terminal() || !log::interactive() {
+        return;
+    }
+    print!(
+        "\r{} {} {}",
+        "[machinegen]".bright_blue().bold(),
+        label.bright_blue(),
+        message.bright_blue()
+    );
+    let _ = io::stdout().flush();
+    PROGRESS_DRAWN.store(true, Ordering::Relaxed);
+}
+
+/// Ends the progress line drawn by `progress`, if any.
+pub fn progress_done() {
+    if PROGRESS_DRAWN.swap(false, Ordering::Relaxed) {
+        println!();
+    }
+}
+
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Runs `command` through `log::run`, reporting whether it succeeded.
+pub fn call_with_stdout(
+    command: &mut process::Command,
+    success_message: &str,
+    error_message: &str,
+) -> bool {
+    let exit_code = match log::run(command) {
+        Ok(code) => code,
+        Err(error) => {
+            log::error(&format!("{}", error));
+            return false;
+        }
+    };
+
+    if exit_code.success() {
+        log::success(success_message);
+        true
+    } else {
+        log::error(error_message);
+        false
+    }
+}
+
+/// Asks a yes/no question on the terminal, defaulting to no.
+/// Without a terminal to ask on, the caller has to be told to skip the question explicitly.
+pub fn confirm(question: &str) -> Result<bool, MachinegenError> {
+    if !io::stdin().is_terminal() {
+        return Err(MachinegenError::Precondition(String::from(
+            "Can't ask for confirmation without a terminal, use --yes to proceed anyway.",
+        )));
+    }
+
+    print!(
+        "{} {} {} ",
+        "[machinegen]".yellow().bold(),
+        question.yellow(),
+        "[y/N]".bold()
+    );
+    io::stdout()
+        .flush()
+        .map_err(|error| MachinegenError::io(Path::new("stdout"), error))?;
+
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .map_err(|error| MachinegenError::io(Path::new("stdin"), error))?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// A `terraform` invocation running inside `project`. Runtime dependencies pulled into
+/// `.machinegen/deps/bin` take precedence over the ones found in `PATH`.
+pub fn terraform_command(project: &Path) -> process::Command {
+    let mut paths = vec![workspace().bin()];
+    if let Some(path) = env::var_os("PATH") {
+        paths.extend(env::split_paths(&path));
+    }
+
+    let mut command = process::Command::new("terraform");
+    command.current_dir(project);
+    if let Ok(path) = env::join_paths(paths) {
+        command.env("PATH", path);
+    }
+    command
+}
+
+/// Makes `workspace` the one every command works in, set once before running any of them.
+pub fn set_workspace(workspace: Workspace) {
+    WORKSPACE
+        .set(workspace)
+        .expect("The workspace is only set once, at startup.");
+}
+
+pub fn workspace() -> &'static Workspace {
+    WORKSPACE
+        .get()
+        .expect("The workspace is set at startup, before running any command.")
+}
+
+pub fn sha256_file(path: &Path) -> Result<String, io::Error> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Reads a `key = value` lock file, as written by `write_lock`. Missing files read as empty.
+pub fn read_lock(path: &Path) -> HashMap<String, String> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once(" = "))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+pub fn write_lock(path: &Path, entries: &[(&str, &str)]) -> Result<(), io::Error> {
+    let content: String = entries
+        .iter()
+        .map(|(key, value)| format!("{} = {}\n", key, value))
+        .collect();
+    fs::write(path, content)
+}
+
+/// Relates the tables of the machine config in the workspace.
+pub fn process_relations() -> Result<MachineData, MachinegenError> {
+    let data = tables::process_relations(&workspace().tables())?;
+    log::debug(&format!(
+        "Related {} config keys, {} templates and {} files out of {}",
+        data.config_keys.len(),
+        data.templates.len(),
+        data.files.len(),
+        workspace().tables().display()
+    ));
+    Ok(data)
+}
+
+pub fn load_table(table_type: TableTypes) -> Result<Tables, MachinegenError> {
+    tables::load_table(&workspace().tables(), table_type)
+}
+
+pub fn mislabeled(table_type: TableTypes) -> MachinegenError {
+    tables::mislabeled(&workspace().tables(), table_type)
+}
+
+pub fn user_config_path() -> Option<(PathBuf, UserConfigFormat)> {
+    user_config::find(&workspace().user())
+}
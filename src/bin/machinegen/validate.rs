@@ -0,0 +1,25 @@
+use machinegen::validate;
+use serde_json::Value;
+
+use super::types::{MachineData, MachinegenError};
+use super::{log, user_config, util};
+
+pub fn run(_sub_match: &clap::ArgMatches) -> Result<(), MachinegenError> {
+    let data = util::process_relations()?;
+    check(&data)?;
+    Ok(())
+}
+
+/// Loads the stored user config and checks it against the machine data,
+/// reporting every problem found at once.
+pub fn check(data: &MachineData) -> Result<Value, MachinegenError> {
+    let config = user_config::load(&util::workspace().user())?;
+    let errors = validate::validate(data, &config);
+
+    if errors.is_empty() {
+        log::success("User config is valid.");
+        Ok(config)
+    } else {
+        Err(MachinegenError::Validation(errors))
+    }
+}
@@ -0,0 +1,369 @@
+//! Referential integrity between the machine config tables.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::tables;
+use crate::types::{Files, MachinegenError, ParsingError, Records, Replace, TableTypes, Template};
+
+/// Checks the references between the machine config tables, which `process_relations`
+/// would otherwise silently drop or merge. `config` is the machine config folder holding
+/// the template sources and the `tables` folder.
+pub fn check(config: &Path) -> Result<Vec<ParsingError>, MachinegenError> {
+    let tables = config.join("tables");
+    let replace: Vec<Replace> = load(&tables, TableTypes::Replace, |record| match record {
+        Records::Replace(record) => Some(record),
+        _ => None,
+    })?;
+    let files: Vec<Files> = load(&tables, TableTypes::Files, |record| match record {
+        Records::Files(record) => Some(record),
+        _ => None,
+    })?;
+    let templates: Vec<Template> = load(&tables, TableTypes::Template, |record| match record {
+        Records::Template(record) => Some(record),
+        _ => None,
+    })?;
+
+    let mut problems: Vec<ParsingError> = Vec::new();
+    let mut problem = |table: TableTypes, row: usize, message: String| {
+        problems.push(ParsingError {
+            // Header first, rows start at line 2
+            message: format!("{}.csv:{}", table.name(), row + 2),
+            cause: message,
+            location: None,
+        })
+    };
+
+    // Replacements pointing at templates that don't exist
+    let template_names: HashSet<&str> = templates
+        .iter()
+        .map(|template| template.name.as_str())
+        .collect();
+    for (row, record) in replace.iter().enumerate() {
+        if record.string.is_empty() || record.string.contains('.') {
+            problem(
+                TableTypes::Replace,
+                row,
+                format!(
+                    "key `{}` is not a valid key name, dots separate the groups of a config path",
+                    record.string
+                ),
+            );
+        }
+        if !template_names.contains(record.template.as_str()) {
+            problem(
+                TableTypes::Replace,
+                row,
+                format!(
+                    "replacement `{}` points at template `{}`, which is not in the templates table",
+                    record.string, record.template
+                ),
+            );
+        }
+    }
+
+    // Template sources missing from the machine config
+    for (row, template) in templates.iter().enumerate() {
+        let source = config.join(&template.source);
+        if !source.is_file() {
+            problem(
+                TableTypes::Template,
+                row,
+                format!(
+                    "template `{}` source {} does not exist",
+                    template.name,
+                    source.display()
+                ),
+            );
+        }
+    }
+
+    // Keys replaced twice in the same template. Keys go by their path in the user config,
+    // keys of different groups may share a name and templates may share a key
+    let mut replaced: HashMap<(String, &str), usize> = HashMap::new();
+    let mut keys: HashMap<String, usize> = HashMap::new();
+    for (row, record) in replace.iter().enumerate() {
+        let path = tables::config_path(&record.config_parent, &record.string);
+        keys.entry(path.clone()).or_insert(row);
+        match replaced.entry((path, record.template.as_str())) {
+            Entry::Occupied(first) => problem(
+                TableTypes::Replace,
+                row,
+                format!(
+                    "key `{}` is already replaced in template `{}` at replace.csv:{}",
+                    first.key().0,
+                    record.template,
+                    first.get() + 2
+                ),
+            ),
+            Entry::Vacant(entry) => {
+                entry.insert(row);
+            }
+        }
+    }
+
+    // Files declared twice, or named like a key of their group
+    let mut file_paths: HashMap<String, usize> = HashMap::new();
+    for (row, record) in files.iter().enumerate() {
+        let group = tables::config_path(&record.config_parent, "files");
+        match file_paths.entry(tables::config_path(&group, &record.name)) {
+            Entry::Occupied(first) => problem(
+                TableTypes::Files,
+                row,
+                format!(
+                    "file `{}` is already declared at files.csv:{}",
+                    first.key(),
+                    first.get() + 2
+                ),
+            ),
+            Entry::Vacant(entry) => {
+                entry.insert(row);
+            }
+        }
+        if let Some(key) = keys.get(&tables::config_path(&record.config_parent, &record.name)) {
+            problem(
+                TableTypes::Files,
+                row,
+                format!(
+                    "file `{}` of group `{}` has the name of the key at replace.csv:{}",
+                    record.name,
+                    record.config_parent,
+                    key + 2
+                ),
+            );
+        }
+    }
+
+    // Templates declared twice
+    let mut template_rows: HashMap<&str, usize> = HashMap::new();
+    for (row, template) in templates.iter().enumerate() {
+        match template_rows.entry(template.name.as_str()) {
+            Entry::Occupied(first) => problem(
+                TableTypes::Template,
+                row,
+                format!(
+                    "template `{}` is already declared at templates.csv:{}",
+                    template.name,
+                    first.get() + 2
+                ),
+            ),
+            Entry::Vacant(entry) => {
+                entry.insert(row);
+            }
+        }
+    }
+
+    // Groups of the user config, the ones holding the groups named by config_parent included
+    let parents = replace
+        .iter()
+        .enumerate()
+        .map(|(row, record)| (TableTypes::Replace, row, record.config_parent.as_str()))
+        .chain(
+            files
+                .iter()
+                .enumerate()
+                .map(|(row, record)| (TableTypes::Files, row, record.config_parent.as_str())),
+        );
+    let mut groups: HashSet<String> = HashSet::new();
+    for (table, row, parent) in parents {
+        match tables::parent_path(parent) {
+            Ok(names) => {
+                for depth in 1..=names.len() {
+                    groups.insert(names[..depth].join("."));
+                }
+            }
+            Err(error) => problem(
+                table,
+                row,
+                format!("config_parent `{}` is not valid: {}", parent, error),
+            ),
+        }
+    }
+
+    // Keys of the same group colliding with each other
+    let file_groups: HashSet<&str> = files
+        .iter()
+        .map(|record| record.config_parent.as_str())
+        .collect();
+    let mut first_file_row: HashMap<&str, usize> = HashMap::new();
+    for (row, record) in files.iter().enumerate() {
+        first_file_row
+            .entry(record.config_parent.as_str())
+            .or_insert(row);
+    }
+    for (row, record) in replace.iter().enumerate() {
+        if record.string == "files" && file_groups.contains(record.config_parent.as_str()) {
+            problem(
+                TableTypes::Replace,
+                row,
+                format!(
+                    "key `files` of group `{}` collides with the files group declared at files.csv:{}",
+                    record.config_parent,
+                    first_file_row[record.config_parent.as_str()] + 2
+                ),
+            );
+        }
+        let path = tables::config_path(&record.config_parent, &record.string);
+        if groups.contains(&path) {
+            problem(
+                TableTypes::Replace,
+                row,
+                format!(
+                    "key `{}` collides with the group of the same name used as a config_parent",
+                    path
+                ),
+            );
+        }
+    }
+
+    // Templates of a system rendering into the same file
+    let mut targets: HashMap<(&str, &Path), usize> = HashMap::new();
+    for (row, template) in templates.iter().enumerate() {
+        let key = (template.system.value(), template.target.as_path());
+        match targets.get(&key) {
+            Some(first) => problem(
+                TableTypes::Template,
+                row,
+                format!(
+                    "template `{}` writes {} target {}, like template `{}` at templates.csv:{}",
+                    template.name,
+                    template.system.value(),
+                    template.target.display(),
+                    templates[*first].name,
+                    first + 2
+                ),
+            ),
+            None => {
+                targets.insert(key, row);
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+fn load<T>(
+    tables: &Path,
+    table_type: TableTypes,
+    unwrap: fn(Records) -> Option<T>,
+) -> Result<Vec<T>, MachinegenError> {
+    let mut records: Vec<T> = Vec::new();
+    for record in tables::load_table(tables, table_type.clone())? {
+        match unwrap(record) {
+            Some(record) => records.push(record),
+            None => return Err(tables::mislabeled(tables, table_type)),
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MachineConfig};
+
+    const TEMPLATES: &str = concat!(
+        "user-data,Guest,templates/user-data,user-data,User data\n",
+        "meta-data,Guest,templates/meta-data,meta-data,Meta data\n",
+    );
+
+    fn problems(replace_rows: &str, files_rows: &str, templates_rows: &str) -> Vec<String> {
+        let config = MachineConfig::new(
+            &format!("{}{}", testing::REPLACE, replace_rows),
+            &format!("{}{}", testing::FILES, files_rows),
+            &format!("{}{}", testing::TEMPLATES, templates_rows),
+        );
+        config.write("templates/user-data", "");
+        config.write("templates/meta-data", "");
+        check(&config.dir)
+            .unwrap()
+            .iter()
+            .map(|problem| format!("{}: {}", problem.message, problem.cause))
+            .collect()
+    }
+
+    #[test]
+    fn valid_layout() {
+        let problems = problems(
+            concat!(
+                "hostName,user-data,true,true,root,Name,string,,,,,\n",
+                "hostName,meta-data,true,true,root,Name,string,,,,,\n",
+                "gateway,user-data,true,true,network,Gateway,ip,,,,,\n",
+                "gateway,user-data,true,true,network.backup,Gateway,ip,,,,,\n",
+            ),
+            "netplan,Guest,network,/etc/netplan/50.yaml,Netplan config\n",
+            TEMPLATES,
+        );
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn duplicates() {
+        let problems = problems(
+            concat!(
+                "hostName,user-data,true,true,root,Name,string,,,,,\n",
+                "hostName,user-data,true,true,root,Name,string,,,,,\n",
+                "netplan,user-data,true,true,network,Netplan,string,,,,,\n",
+            ),
+            concat!(
+                "netplan,Guest,network,/etc/netplan/50.yaml,Netplan config\n",
+                "netplan,Guest,network,/etc/netplan/60.yaml,Netplan config\n",
+            ),
+            &format!(
+                "{}user-data,Host,templates/user-data,main.tf,Terraform\n",
+                TEMPLATES
+            ),
+        );
+        assert_eq!(
+            problems,
+            [
+                "replace.csv:3: key `hostName` is already replaced in template `user-data` at replace.csv:2",
+                "files.csv:2: file `netplan` of group `network` has the name of the key at replace.csv:4",
+                "files.csv:3: file `network.files.netplan` is already declared at files.csv:2",
+                "files.csv:3: file `netplan` of group `network` has the name of the key at replace.csv:4",
+                "templates.csv:4: template `user-data` is already declared at templates.csv:2",
+            ]
+        );
+    }
+
+    #[test]
+    fn references() {
+        let problems = problems(
+            concat!(
+                "host.name,user-data,true,true,root,Name,string,,,,,\n",
+                "hostname,vendor-data,true,true,root,Name,string,,,,,\n",
+                "files,user-data,true,true,network,Files,string,,,,,\n",
+                "network,user-data,true,true,root,Network,string,,,,,\n",
+                "gateway,user-data,true,true,network..backup,Gateway,ip,,,,,\n",
+            ),
+            "netplan,Guest,network,/etc/netplan/50.yaml,Netplan config\n",
+            &format!(
+                "{}{}",
+                TEMPLATES,
+                concat!(
+                    "network-config,Guest,templates/network-config,network-config,Network\n",
+                    "seed,Guest,templates/meta-data,meta-data,Seed\n",
+                )
+            ),
+        );
+        // Sources are named by their full path
+        assert!(
+            problems[2].starts_with("templates.csv:4: template `network-config` source /")
+                && problems[2].ends_with("/templates/network-config does not exist"),
+            "{}",
+            problems[2]
+        );
+        assert_eq!(
+            [&problems[..2], &problems[3..]].concat(),
+            [
+                "replace.csv:2: key `host.name` is not a valid key name, dots separate the groups of a config path",
+                "replace.csv:3: replacement `hostname` points at template `vendor-data`, which is not in the templates table",
+                "replace.csv:6: config_parent `network..backup` is not valid: group names can't be empty",
+                "replace.csv:4: key `files` of group `network` collides with the files group declared at files.csv:2",
+                "replace.csv:5: key `network` collides with the group of the same name used as a config_parent",
+                "templates.csv:5: template `seed` writes guest target meta-data, like template `meta-data` at templates.csv:3",
+            ]
+        );
+    }
+}
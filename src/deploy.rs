@@ -1 +0,0 @@
-pub fn run(_sub_match: &clap::ArgMatches) {}
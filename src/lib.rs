@@ -0,0 +1,31 @@
+//! Machine config handling behind the `machinegen` command line tool.
+//!
+//! A machine config is a folder holding the `replace`, `files` and `templates` tables,
+//! plus the template sources they point at. This crate loads and relates those tables
+//! into [`types::MachineData`], checks them and a user config against each other, and
+//! renders the templates, without printing anything or assuming where the folder lives.
+//! [`workspace::Workspace`] knows where it is kept inside a `.machinegen` folder.
+//! Errors are [`types::MachinegenError`], a regular [`std::error::Error`]:
+//!
+//! ```no_run
+//! use machinegen::workspace::Workspace;
+//!
+//! let workspace = Workspace::resolve(None)?;
+//! let data = machinegen::tables::process_relations(&workspace.tables())?;
+//! let user = machinegen::user_config::load(&workspace.config())?;
+//! let problems = machinegen::validate::validate(&data, &user);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod check;
+pub mod render;
+pub mod schema;
+pub mod skeleton;
+pub mod tables;
+#[cfg(test)]
+mod testing;
+pub mod types;
+pub mod user_config;
+pub mod validate;
+pub mod values;
+pub mod workspace;
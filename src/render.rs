@@ -0,0 +1,465 @@
+//! Template rendering: filling the machine config templates with user config values.
+
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::types::{
+    ConfigPrimitives, MachineData, MachinegenError, ReplaceEntry, System, TemplateEntry,
+};
+use crate::values;
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex = Regex::new(r"\{\{\s*([^{}\s]+)\s*\}\}").unwrap();
+}
+
+/// A value taken from the user config, ready to be written into a template.
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum Replacement {
+    Single(String),
+    List(Vec<String>),
+}
+
+/// Renders every template of `system` into the `output` folder, returning the written paths.
+/// Template sources are relative to the `sources` folder. Problems are collected over all
+/// templates before failing.
+pub fn render_system(
+    data: &MachineData,
+    config: &Value,
+    system: &System,
+    sources: &Path,
+    output: &Path,
+) -> Result<Vec<PathBuf>, MachinegenError> {
+    let mut names: Vec<&String> = data
+        .templates
+        .iter()
+        .filter(|(_, template)| template.system.value() == system.value())
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+
+    let mut errors: Vec<String> = Vec::new();
+    let mut rendered: Vec<(PathBuf, String)> = Vec::new();
+
+    for name in names {
+        let template = &data.templates[name];
+
+        if template
+            .target
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            errors.push(format!(
+                "template `{}`: target {} must be a relative path",
+                name,
+                template.target.display()
+            ));
+            continue;
+        }
+
+        match render(name, template, sources, config) {
+            Ok(content) => rendered.push((output.join(&template.target), content)),
+            Err(template_errors) => errors.extend(template_errors),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(MachinegenError::Config(format!(
+            "Could not render the {} templates, {} problem{} found:\n  {}",
+            system.value(),
+            errors.len(),
+            if errors.len() == 1 { "" } else { "s" },
+            errors.join("\n  ")
+        )));
+    }
+
+    let mut written: Vec<PathBuf> = Vec::new();
+    for (path, content) in rendered {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|error| MachinegenError::io(parent, error))?;
+        }
+        fs::write(&path, content).map_err(|error| MachinegenError::io(&path, error))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Renders a single template, replacing every `{{ key }}` placeholder with its user config value.
+/// Keys of groups are named by their dotted path, like `{{ nics.primary.mac }}`, or by their
+/// bare name as long as no other key of the template shares it.
+///
+/// Lines holding a list placeholder (a key that is not unique) are repeated once per item,
+/// so `  - {{ ssh_keys }}` becomes one YAML list entry per key, and vanish if the list is empty.
+pub fn render(
+    name: &str,
+    template: &TemplateEntry,
+    sources: &Path,
+    config: &Value,
+) -> Result<String, Vec<String>> {
+    let mut errors: Vec<String> = Vec::new();
+
+    let source = sources.join(&template.source);
+    let content = match fs::read_to_string(&source) {
+        Ok(content) => content,
+        Err(error) => {
+            return Err(vec![format!(
+                "template `{}`: could not read {}: {}",
+                name,
+                source.display(),
+                error
+            )])
+        }
+    };
+
+    let mut values: HashMap<&str, Replacement> = HashMap::new();
+    // Keys without a value, already reported, so their placeholders are not reported again
+    let mut failed: HashSet<&str> = HashSet::new();
+    for (key, value) in replacements(template, config) {
+        match value {
+            Ok(value) => {
+                values.insert(key, value);
+            }
+            Err(error) => {
+                failed.insert(key);
+                errors.push(format!("template `{}`: {}", name, error));
+            }
+        }
+    }
+
+    // Bare names of keys of groups, unless several keys of the template share one
+    let mut aliases: HashMap<&str, Option<&str>> = HashMap::new();
+    for path in values.keys().chain(failed.iter()) {
+        if let Some((_, key)) = path.rsplit_once('.') {
+            aliases
+                .entry(key)
+                .and_modify(|alias| *alias = None)
+                .or_insert(Some(*path));
+        }
+    }
+    let mut placeholders: HashMap<&str, &Replacement> =
+        values.iter().map(|(path, value)| (*path, value)).collect();
+    for (key, path) in &aliases {
+        if let Some(path) = path {
+            placeholders.entry(key).or_insert(&values[path]);
+        }
+    }
+
+    let mut output = String::with_capacity(content.len());
+
+    for (index, line) in content.split_inclusive('\n').enumerate() {
+        let mut repeat: Option<usize> = None;
+
+        for captures in PLACEHOLDER.captures_iter(line) {
+            match placeholders.get(&captures[1]) {
+                Some(Replacement::List(items)) => match repeat {
+                    Some(count) if count != items.len() => errors.push(format!(
+                        "template `{}`: line {} mixes lists of different lengths",
+                        name,
+                        index + 1
+                    )),
+                    _ => repeat = Some(items.len()),
+                },
+                Some(Replacement::Single(_)) => {}
+                None if failed.contains(&captures[1])
+                    || matches!(aliases.get(&captures[1]), Some(Some(path)) if failed.contains(path)) => {}
+                None if aliases.get(&captures[1]) == Some(&None) => errors.push(format!(
+                    "template `{}`: placeholder `{}` at line {} is ambiguous, several keys are named `{}`, use their full path",
+                    name,
+                    &captures[0],
+                    index + 1,
+                    &captures[1]
+                )),
+                None => errors.push(format!(
+                    "template `{}`: unreplaced placeholder `{}` at line {}, `{}` is not in the replace table for this template",
+                    name,
+                    &captures[0],
+                    index + 1,
+                    &captures[1]
+                )),
+            }
+        }
+
+        match repeat {
+            None => output.push_str(&substitute(line, &placeholders, None)),
+            Some(count) => {
+                for item in 0..count {
+                    output.push_str(&substitute(line, &placeholders, Some(item)));
+                }
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(output)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Values the placeholders of a template are replaced with, by the dotted path of their key,
+/// or why a value can't be taken from the user config.
+pub fn replacements<'a>(
+    template: &'a TemplateEntry,
+    config: &Value,
+) -> BTreeMap<&'a str, Result<Replacement, String>> {
+    template
+        .replacements
+        .iter()
+        .map(|(key, entry)| (key.as_str(), lookup(key, entry, config)))
+        .collect()
+}
+
+fn substitute(line: &str, values: &HashMap<&str, &Replacement>, item: Option<usize>) -> String {
+    PLACEHOLDER
+        .replace_all(line, |captures: &Captures| match values.get(&captures[1]) {
+            Some(Replacement::Single(value)) => value.clone(),
+            Some(Replacement::List(items)) => item
+                .and_then(|item| items.get(item))
+                .cloned()
+                .unwrap_or_default(),
+            None => captures[0].to_string(),
+        })
+        .into_owned()
+}
+
+/// Finds the value of a replacement in the user config at the dotted `path` of its key,
+/// falling back on its default when the user config leaves it out.
+fn lookup(path: &str, entry: &ReplaceEntry, config: &Value) -> Result<Replacement, String> {
+    let value = path
+        .split('.')
+        .try_fold(config, |group, name| group.get(name));
+
+    let primitive = entry
+        .value_type
+        .clone()
+        .unwrap_or(ConfigPrimitives::NoValue);
+    let default = values::default_value(&primitive, &entry.constraints, entry.unique);
+    let value = match value {
+        None | Some(Value::Null) => default.as_ref(),
+        value => value,
+    };
+    match value {
+        None | Some(Value::Null) => {
+            if entry.mandatory {
+                Err(format!(
+                    "key `{}` is mandatory but missing from the user config",
+                    path
+                ))
+            } else if entry.unique {
+                Ok(Replacement::Single(String::new()))
+            } else {
+                Ok(Replacement::List(Vec::new()))
+            }
+        }
+        Some(Value::Array(items)) if !entry.unique => {
+            let mut list: Vec<String> = Vec::new();
+            for (index, item) in items.iter().enumerate() {
+                match values::check(&primitive, &entry.constraints, item) {
+                    Ok(item) => list.push(item),
+                    Err(error) => return Err(format!("key `{}[{}]`: {}", path, index, error)),
+                }
+            }
+            Ok(Replacement::List(list))
+        }
+        Some(value) if entry.unique => match values::check(&primitive, &entry.constraints, value) {
+            Ok(value) => Ok(Replacement::Single(value)),
+            Err(error) => Err(format!("key `{}`: {}", path, error)),
+        },
+        Some(_) => Err(format!("key `{}` must be a list", path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MachineConfig};
+    use serde_json::json;
+
+    const TEMPLATES: &str = concat!(
+        "user-data,Guest,templates/user-data,user-data,User data\n",
+        "meta-data,Guest,templates/meta-data,meta-data,Meta data\n",
+        "main.tf,Host,templates/main.tf,main.tf,Terraform project\n",
+    );
+
+    fn machine_config(replace_rows: &str) -> MachineConfig {
+        MachineConfig::new(
+            &format!("{}{}", testing::REPLACE, replace_rows),
+            testing::FILES,
+            &format!("{}{}", testing::TEMPLATES, TEMPLATES),
+        )
+    }
+
+    fn rendered(config: &MachineConfig, name: &str, source: &str, user: Value) -> String {
+        config.write(&format!("templates/{}", name), source);
+        let data = config.data().unwrap();
+        render(name, &data.templates[name], &config.dir, &user).unwrap()
+    }
+
+    #[test]
+    fn keys_shared_by_templates() {
+        let config = machine_config(concat!(
+            "hostName,user-data,true,true,root,Name,string,,,,,\n",
+            "hostName,meta-data,true,true,root,Name,string,,,,,\n",
+            "memory,main.tf,true,true,root,Memory,size,,,,,\n",
+        ));
+        config.write("templates/user-data", "hostname: {{ hostName }}\n");
+        config.write("templates/meta-data", "local-hostname: {{hostName}}\n");
+        config.write("templates/main.tf", "memory = {{ memory }}\n");
+        let data = config.data().unwrap();
+        let output = config.dir.join("build");
+
+        let written = render_system(
+            &data,
+            &json!({"hostName": "vm", "memory": "1G"}),
+            &System::Guest,
+            &config.dir,
+            &output,
+        )
+        .unwrap();
+        assert_eq!(
+            written,
+            [output.join("meta-data"), output.join("user-data")]
+        );
+        assert_eq!(
+            fs::read_to_string(output.join("user-data")).unwrap(),
+            "hostname: vm\n"
+        );
+        assert_eq!(
+            fs::read_to_string(output.join("meta-data")).unwrap(),
+            "local-hostname: vm\n"
+        );
+        // Host templates are rendered apart
+        assert!(!output.join("main.tf").exists());
+    }
+
+    #[test]
+    fn groups_and_aliases() {
+        let config = machine_config(concat!(
+            "gateway,user-data,true,true,network,Gateway,ip,,,,,\n",
+            "mac,user-data,true,true,nics.primary,MAC,mac,,,,,\n",
+            "mac,user-data,true,true,nics.backup,MAC,mac,,,,,\n",
+        ));
+        let user = json!({
+            "network": {"gateway": "10.0.0.1"},
+            "nics": {"primary": {"mac": "52:54:00:AB:CD:EF"}, "backup": {"mac": "52:54:00:00:00:01"}},
+        });
+        assert_eq!(
+            rendered(
+                &config,
+                "user-data",
+                "{{ gateway }} {{ network.gateway }} {{ nics.primary.mac }}\n",
+                user.clone()
+            ),
+            "10.0.0.1 10.0.0.1 52:54:00:ab:cd:ef\n"
+        );
+
+        config.write("templates/user-data", "{{ mac }}\n");
+        let data = config.data().unwrap();
+        let errors = render(
+            "user-data",
+            &data.templates["user-data"],
+            &config.dir,
+            &user,
+        )
+        .unwrap_err();
+        assert!(errors[0].contains("is ambiguous"), "{:?}", errors);
+    }
+
+    #[test]
+    fn lists_repeat_their_lines() {
+        let config = machine_config(concat!(
+            "ssh_keys,user-data,false,false,root,Keys,string,,,,,\n",
+            "users,user-data,false,false,root,Users,string,,,,,\n",
+            "ports,user-data,false,false,root,Ports,uint,22|80,,,,\n",
+        ));
+        let source = "keys:\n  - {{ ssh_keys }}\nports: {{ ports }}\nend\n";
+        assert_eq!(
+            rendered(
+                &config,
+                "user-data",
+                source,
+                json!({"ssh_keys": ["a", "b"]})
+            ),
+            "keys:\n  - a\n  - b\nports: 22\nports: 80\nend\n"
+        );
+        // Empty lists take their lines with them
+        assert_eq!(
+            rendered(&config, "user-data", source, json!({"ports": []})),
+            "keys:\nend\n"
+        );
+
+        config.write("templates/user-data", "{{ ssh_keys }}: {{ users }}\n");
+        let data = config.data().unwrap();
+        let user = json!({"ssh_keys": ["a", "b"], "users": ["c"]});
+        let errors = render(
+            "user-data",
+            &data.templates["user-data"],
+            &config.dir,
+            &user,
+        )
+        .unwrap_err();
+        assert!(
+            errors[0].contains("lists of different lengths"),
+            "{:?}",
+            errors
+        );
+    }
+
+    #[test]
+    fn problems_of_every_template() {
+        let config = machine_config(concat!(
+            "hostname,user-data,true,true,root,Name,string,,,,,\n",
+            "memory,meta-data,true,true,root,Memory,size,,,,,\n",
+        ));
+        config.write("templates/user-data", "{{ hostname }} {{ domain }}\n");
+        config.write("templates/meta-data", "{{ memory }}\n");
+        let data = config.data().unwrap();
+        let output = config.dir.join("build");
+
+        let error = render_system(
+            &data,
+            &json!({"memory": "lots"}),
+            &System::Guest,
+            &config.dir,
+            &output,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("3 problems"), "{}", error);
+        assert!(
+            error.contains("template `meta-data`: key `memory`"),
+            "{}",
+            error
+        );
+        assert!(error.contains("key `hostname` is mandatory"), "{}", error);
+        assert!(
+            error.contains("unreplaced placeholder `{{ domain }}` at line 1"),
+            "{}",
+            error
+        );
+        // Nothing is written unless every template renders
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn optional_groups_left_out() {
+        let config = machine_config(concat!(
+            "gateway,user-data,false,true,network,Gateway,ip,,,,,\n",
+            "mtu,user-data,true,true,network,MTU,uint,1500,,,,\n",
+        ));
+        assert_eq!(
+            rendered(
+                &config,
+                "user-data",
+                "gateway: {{ gateway }}\nmtu: {{ network.mtu }}\n",
+                json!({})
+            ),
+            "gateway: \nmtu: 1500\n"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! JSON Schema of the user config, for editors and external tooling.
+
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+use crate::types::{ConfigEntry, ConfigPrimitives, MachineData};
+use crate::values;
+
+const DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Builds a JSON Schema (draft 2020-12) document for the user config out of the machine data.
+pub fn generate(data: &MachineData, id: &str) -> Value {
+    let mut schema = object_schema(&data.config_keys);
+    let root = schema
+        .as_object_mut()
+        .expect("Object schemas are always JSON objects.");
+
+    // User configs may point editors to this schema themselves
+    if let Some(properties) = root.get_mut("properties").and_then(Value::as_object_mut) {
+        properties.insert(
+            String::from("$schema"),
+            json!({ "type": "string", "description": "Schema of this file, for editors." }),
+        );
+    }
+    root.insert(String::from("$schema"), json!(DRAFT));
+    root.insert(String::from("$id"), json!(id));
+    root.insert(String::from("title"), json!("machinegen user config"));
+    root.insert(
+        String::from("description"),
+        json!("User config for a machine, generated by machinegen from the machine config tables."),
+    );
+    schema
+}
+
+fn object_schema(entries: &HashMap<String, ConfigEntry>) -> Value {
+    let mut properties = Map::new();
+    let mut required: Vec<&String> = Vec::new();
+
+    for (key, entry) in entries {
+        properties.insert(key.clone(), entry_schema(entry));
+        if entry.mandatory && entry.constraints.default.is_none() {
+            required.push(key);
+        }
+    }
+    required.sort();
+
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+fn entry_schema(entry: &ConfigEntry) -> Value {
+    let mut schema = match &entry.children {
+        Some(children) => object_schema(children),
+        None => {
+            let mut schema = primitive_schema(&entry.value);
+            constrain(&mut schema, entry);
+            schema
+        }
+    };
+
+    if !entry.unique {
+        schema = json!({
+            "type": "array",
+            "items": schema,
+        });
+    }
+
+    if let Some(schema) = schema.as_object_mut() {
+        schema.insert(String::from("description"), json!(entry.description));
+        let primitive = entry.value.clone().unwrap_or(ConfigPrimitives::NoValue);
+        if let Some(default) = values::default_value(&primitive, &entry.constraints, entry.unique) {
+            schema.insert(String::from("default"), default);
+        }
+    }
+    schema
+}
+
+/// Adds the constraints of a key to the schema of its values. Bounds apply to numbers and
+/// to the length of strings, like machinegen checks them.
+fn constrain(schema: &mut Value, entry: &ConfigEntry) {
+    let primitive = entry.value.clone().unwrap_or(ConfigPrimitives::NoValue);
+    let constraints = &entry.constraints;
+    let schema = match schema.as_object_mut() {
+        Some(schema) => schema,
+        None => return,
+    };
+
+    let (numbers, strings) = match primitive {
+        ConfigPrimitives::I32
+        | ConfigPrimitives::I64
+        | ConfigPrimitives::U32
+        | ConfigPrimitives::U64
+        | ConfigPrimitives::F32
+        | ConfigPrimitives::F64
+        | ConfigPrimitives::Size => (true, false),
+        ConfigPrimitives::NoValue | ConfigPrimitives::Array => (true, true),
+        _ => (false, true),
+    };
+    for (bound, number, length) in [
+        (constraints.min, "minimum", "minLength"),
+        (constraints.max, "maximum", "maxLength"),
+    ] {
+        if let Some(bound) = bound {
+            if numbers {
+                // Whole bounds read better as integers, and integer types expect them
+                let bound = if bound.fract() == 0.0 && bound.abs() < i64::MAX as f64 {
+                    json!(bound as i64)
+                } else {
+                    json!(bound)
+                };
+                schema.insert(String::from(number), bound);
+            }
+            if strings {
+                let bound = if length == "minLength" {
+                    bound.ceil()
+                } else {
+                    bound.floor()
+                };
+                schema.insert(String::from(length), json!(bound.max(0.0) as u64));
+            }
+        }
+    }
+
+    // Sizes keep their own pattern, the one of the key applies to their bytes
+    if let Some(pattern) = constraints.pattern.as_ref().filter(|_| strings) {
+        schema.insert(String::from("pattern"), json!(pattern));
+    }
+    // Choices are compared to normalized values, which an enum can't do for the types with
+    // several ways to write a value, like `2G` and `2048M`
+    let normalized = matches!(
+        primitive,
+        ConfigPrimitives::Path
+            | ConfigPrimitives::Ip
+            | ConfigPrimitives::Cidr
+            | ConfigPrimitives::Mac
+            | ConfigPrimitives::Size
+    );
+    if let Some(choices) = constraints.choices.as_ref().filter(|_| !normalized) {
+        let choices: Vec<Value> = choices
+            .iter()
+            .map(|choice| values::cell(&primitive, choice))
+            .collect();
+        schema.insert(String::from("enum"), json!(choices));
+    }
+}
+
+fn primitive_schema(value: &Option<ConfigPrimitives>) -> Value {
+    match value {
+        Some(ConfigPrimitives::String) => json!({ "type": "string" }),
+        Some(ConfigPrimitives::I32) | Some(ConfigPrimitives::I64) => json!({ "type": "integer" }),
+        Some(ConfigPrimitives::U32) | Some(ConfigPrimitives::U64) => {
+            json!({ "type": "integer", "minimum": 0 })
+        }
+        Some(ConfigPrimitives::F32) | Some(ConfigPrimitives::F64) => json!({ "type": "number" }),
+        Some(ConfigPrimitives::Bool) => json!({ "type": "boolean" }),
+        Some(ConfigPrimitives::Path) => json!({ "type": "string", "minLength": 1 }),
+        Some(ConfigPrimitives::Ip) => json!({
+            "type": "string",
+            "anyOf": [{ "format": "ipv4" }, { "format": "ipv6" }],
+        }),
+        Some(ConfigPrimitives::Cidr) => json!({
+            "type": "string",
+            "pattern": "^[0-9A-Fa-f.:]+/[0-9]{1,3}$",
+        }),
+        Some(ConfigPrimitives::Mac) => json!({
+            "type": "string",
+            "pattern": "^[0-9A-Fa-f]{2}((:[0-9A-Fa-f]{2}){5}|(-[0-9A-Fa-f]{2}){5})$",
+        }),
+        Some(ConfigPrimitives::Size) => json!({
+            "type": ["integer", "string"],
+            "minimum": 0,
+            "pattern": "^[0-9]+(\\.[0-9]+)?\\s*([KMGTPkmgtp](i?B)?|[Bb])?$",
+        }),
+        Some(ConfigPrimitives::Enum(choices)) => json!({ "type": "string", "enum": choices }),
+        // Arrays are laid out by the caller according to `unique`, their items are scalars
+        Some(ConfigPrimitives::NoValue) | Some(ConfigPrimitives::Array) | None => {
+            json!({ "type": ["string", "number", "boolean"] })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn schema(rows: &str) -> Value {
+        generate(
+            &testing::machine_data(rows),
+            "https://example.com/user.json",
+        )
+    }
+
+    #[test]
+    fn document() {
+        let schema = schema("hostname,user-data,true,true,root,Name,string,,,,,\n");
+        assert_eq!(schema["$schema"], DRAFT);
+        assert_eq!(schema["$id"], "https://example.com/user.json");
+        assert_eq!(schema["additionalProperties"], false);
+        // User configs may name their schema
+        assert_eq!(schema["properties"]["$schema"]["type"], "string");
+        assert_eq!(schema["required"], json!(["hostname"]));
+    }
+
+    #[test]
+    fn required_keys() {
+        let schema = schema(concat!(
+            "hostname,user-data,true,true,root,Name,string,,,,,\n",
+            "memory,user-data,true,true,root,Memory,size,2G,,,,\n",
+            "domain,user-data,false,true,root,Domain,string,,,,,\n",
+            "gateway,user-data,true,true,network,Gateway,ip,,,,,\n",
+        ));
+        assert_eq!(schema["required"], json!(["hostname", "network"]));
+        assert_eq!(schema["properties"]["memory"]["default"], "2G");
+        let network = &schema["properties"]["network"];
+        assert_eq!(network["type"], "object");
+        assert_eq!(network["required"], json!(["gateway"]));
+        assert_eq!(network["properties"]["gateway"]["description"], "Gateway");
+    }
+
+    #[test]
+    fn constraints() {
+        let schema = schema(concat!(
+            "cpus,user-data,true,true,root,CPUs,uint,,1,64,,\n",
+            "name,user-data,true,true,root,Name,string,,2,8.5,^[a-z]+$,\n",
+            "flavor,user-data,true,true,root,Flavor,,,,,,small|large\n",
+            "ports,user-data,true,false,root,Ports,uint,22,,,,\n",
+            "cores,user-data,true,true,root,Cores,uint,,,,,1|2|4\n",
+            "memory,user-data,true,true,root,Memory,size,2G,,,,2G|4096M\n",
+        ));
+        let properties = &schema["properties"];
+        assert_eq!(properties["cpus"]["type"], "integer");
+        assert_eq!(properties["cpus"]["minimum"], 1);
+        assert_eq!(properties["cpus"]["maximum"], 64);
+        assert_eq!(properties["name"]["minLength"], 2);
+        assert_eq!(properties["name"]["maxLength"], 8);
+        assert_eq!(properties["name"]["pattern"], "^[a-z]+$");
+        assert_eq!(properties["flavor"]["enum"], json!(["small", "large"]));
+        assert_eq!(properties["cores"]["enum"], json!([1, 2, 4]));
+        // Sizes are compared in bytes, however they're written
+        assert_eq!(properties["memory"].get("enum"), None);
+        assert_eq!(properties["memory"]["default"], "2G");
+        // Keys that aren't unique take lists, defaults included
+        assert_eq!(properties["ports"]["type"], "array");
+        assert_eq!(properties["ports"]["items"]["type"], "integer");
+        assert_eq!(properties["ports"]["default"], json!([22]));
+    }
+
+    #[test]
+    fn optional_groups() {
+        let schema = schema(concat!(
+            "gateway,user-data,false,true,network,Gateway,ip,,,,,\n",
+            "mtu,user-data,true,true,network,MTU,uint,1500,,,,\n",
+            "mac,user-data,true,true,nics.primary,MAC,mac,,,,,\n",
+        ));
+        assert_eq!(schema["required"], json!(["nics"]));
+        assert_eq!(schema["properties"]["network"]["required"], json!([]));
+        assert_eq!(schema["properties"]["nics"]["required"], json!(["primary"]));
+    }
+}
@@ -0,0 +1,299 @@
+//! User config skeletons: a commented starting point for the user config, laid out after the
+//! keys and files groups the machine config tables declare.
+
+use std::collections::HashMap;
+
+use crate::types::{ConfigEntry, ConfigPrimitives, MachineData, UserConfigFormat};
+use crate::values;
+
+const INDENT: &str = "    ";
+
+/// Builds a commented user config skeleton out of the machine data config keys.
+/// Every key carries its description and constraints as a comment, mandatory keys are marked
+/// as such, keys with a default get it as their value and keys that are not unique are laid
+/// out as arrays.
+pub fn generate(data: &MachineData, format: UserConfigFormat) -> String {
+    let mut skeleton = String::new();
+
+    skeleton.push_str(&format!(
+        "// User config skeleton generated by machinegen from the machine config tables.\n\
+        // Fill in every key marked as mandatory, the rest can be removed if not needed.\n\
+        // Save it as user.{} and pull it with `machinegen pull config --user <path>`.\n",
+        format.extension()
+    ));
+    write_object(&mut skeleton, &data.config_keys, format, 0);
+    skeleton.push('\n');
+    skeleton
+}
+
+fn write_object(
+    output: &mut String,
+    entries: &HashMap<String, ConfigEntry>,
+    format: UserConfigFormat,
+    depth: usize,
+) {
+    if entries.is_empty() {
+        output.push_str("{}");
+        return;
+    }
+
+    // Sorted so the skeleton is stable between runs, with groups after plain keys
+    let mut keys: Vec<&String> = entries.keys().collect();
+    keys.sort_by_key(|key| (entries[*key].children.is_some(), key.as_str()));
+
+    output.push_str("{\n");
+    for (index, key) in keys.iter().enumerate() {
+        let entry = &entries[*key];
+        let indent = INDENT.repeat(depth + 1);
+
+        output.push_str(&format!("{}// {}", indent, comment(&entry.description)));
+        if entry.mandatory && entry.constraints.default.is_none() {
+            output.push_str(" (mandatory)");
+        }
+        if !entry.unique {
+            output.push_str(" (list)");
+        }
+        output.push_str(&constraints(entry));
+        output.push('\n');
+
+        output.push_str(&format!("{}{}: ", indent, format_key(key, format)));
+        match &entry.children {
+            Some(children) => {
+                if entry.unique {
+                    write_object(output, children, format, depth + 1);
+                } else {
+                    output.push('[');
+                    write_object(output, children, format, depth + 1);
+                    output.push(']');
+                }
+            }
+            None => output.push_str(&placeholder(entry)),
+        }
+
+        // JSON5 is fine with trailing commas, JSONC parsers are not always
+        if index + 1 < keys.len() || format == UserConfigFormat::Json5 {
+            output.push(',');
+        }
+        output.push('\n');
+    }
+    output.push_str(&format!("{}}}", INDENT.repeat(depth)));
+}
+
+fn format_key(key: &str, format: UserConfigFormat) -> String {
+    let is_identifier = key
+        .chars()
+        .next()
+        .map(|first| first.is_ascii_alphabetic() || first == '_' || first == '$')
+        .unwrap_or(false)
+        && key.chars().all(|character| {
+            character.is_ascii_alphanumeric() || character == '_' || character == '$'
+        });
+
+    if format == UserConfigFormat::Json5 && is_identifier {
+        key.to_string()
+    } else {
+        serde_json::to_string(key).unwrap_or_else(|_| format!("\"{}\"", key))
+    }
+}
+
+fn placeholder(entry: &ConfigEntry) -> String {
+    let primitive = entry.value.clone().unwrap_or(ConfigPrimitives::NoValue);
+    if let Some(default) = values::default_value(&primitive, &entry.constraints, entry.unique) {
+        return serde_json::to_string(&default).unwrap_or_else(|_| String::from("\"\""));
+    }
+    // Without a default, the first choice is the one value known to validate
+    if let Some(choice) = entry
+        .constraints
+        .choices
+        .as_ref()
+        .and_then(|choices| choices.first())
+    {
+        let choice = values::cell(&primitive, choice).to_string();
+        return if entry.unique {
+            choice
+        } else {
+            format!("[{}]", choice)
+        };
+    }
+
+    let value = match &entry.value {
+        Some(ConfigPrimitives::I32)
+        | Some(ConfigPrimitives::I64)
+        | Some(ConfigPrimitives::U32)
+        | Some(ConfigPrimitives::U64)
+        | Some(ConfigPrimitives::Size) => String::from("0"),
+        Some(ConfigPrimitives::F32) | Some(ConfigPrimitives::F64) => String::from("0.0"),
+        Some(ConfigPrimitives::Bool) => String::from("false"),
+        Some(ConfigPrimitives::Enum(choices)) => {
+            serde_json::to_string(&choices[0]).unwrap_or_else(|_| String::from("\"\""))
+        }
+        _ => String::from("\"\""),
+    };
+
+    if entry.unique {
+        value
+    } else {
+        format!("[{}]", value)
+    }
+}
+
+/// The constraints of a key, as notes following its description. Defaults are the
+/// placeholder values themselves.
+fn constraints(entry: &ConfigEntry) -> String {
+    let constraints = &entry.constraints;
+    let mut notes: Vec<String> = Vec::new();
+    if constraints.default.is_some() {
+        notes.push(String::from("default"));
+    }
+    if let Some(min) = constraints.min {
+        notes.push(format!("min {}", min));
+    }
+    if let Some(max) = constraints.max {
+        notes.push(format!("max {}", max));
+    }
+    if let Some(pattern) = &constraints.pattern {
+        notes.push(format!("matching {}", pattern));
+    }
+    if let Some(choices) = &constraints.choices {
+        notes.push(format!("one of {}", choices.join("|")));
+    }
+    notes
+        .iter()
+        .map(|note| format!(" ({})", comment(note)))
+        .collect()
+}
+
+/// Descriptions come from the tables, keep them to a single comment line.
+fn comment(description: &str) -> String {
+    description
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing, user_config};
+
+    const KEYS: &str = concat!(
+        "hostname,user-data,true,true,root,Name of the machine,string,,,,,\n",
+        "memory,user-data,true,true,root,Memory,size,2G,,,,\n",
+        "flavor,user-data,false,true,root,Flavor,,,,,,small|large\n",
+        "ssh_keys,user-data,false,false,root,Keys,,,,,,\n",
+        "gateway,user-data,true,true,network,Gateway,ip,,,,,\n",
+    );
+
+    #[test]
+    fn keys() {
+        let skeleton = generate(&testing::machine_data(KEYS), UserConfigFormat::Jsonc);
+        let body = skeleton.split_once("\n{\n").unwrap().1;
+        assert_eq!(
+            body,
+            concat!(
+                "    // Flavor (one of small|large)\n",
+                "    \"flavor\": \"small\",\n",
+                "    // Name of the machine (mandatory)\n",
+                "    \"hostname\": \"\",\n",
+                "    // Memory (default)\n",
+                "    \"memory\": \"2G\",\n",
+                "    // Keys (list)\n",
+                "    \"ssh_keys\": [\"\"],\n",
+                "    // Group of config entries (mandatory)\n",
+                "    \"network\": {\n",
+                "        // Gateway (mandatory)\n",
+                "        \"gateway\": \"\"\n",
+                "    }\n",
+                "}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn parsable() {
+        let data = testing::machine_data(KEYS);
+        for format in [UserConfigFormat::Jsonc, UserConfigFormat::Json5] {
+            let skeleton = generate(&data, format);
+            let value = user_config::parse(&skeleton, format).unwrap();
+            assert_eq!(value["memory"], "2G");
+            assert_eq!(value["network"]["gateway"], "");
+        }
+        let json5 = generate(&data, UserConfigFormat::Json5);
+        assert!(json5.contains("\n    hostname: \"\",\n"), "{}", json5);
+        assert!(
+            json5.contains("\n        gateway: \"\",\n    },\n}"),
+            "{}",
+            json5
+        );
+    }
+
+    #[test]
+    fn placeholders_of_types() {
+        let data = testing::machine_data(concat!(
+            "count,user-data,true,true,root,Count,uint,,,,,\n",
+            "ratio,user-data,true,true,root,Ratio,float,,,,,\n",
+            "enabled,user-data,true,true,root,Enabled,bool,,,,,\n",
+            "sizes,user-data,true,false,root,Sizes,size,,,,,\n",
+            "ports,user-data,true,false,root,Ports,uint,22|80,,,,\n",
+            "mode,user-data,true,false,root,Mode,uint,,,,,1|2\n",
+        ));
+        let value = user_config::parse(
+            &generate(&data, UserConfigFormat::Jsonc),
+            UserConfigFormat::Jsonc,
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "count": 0,
+                "ratio": 0.0,
+                "enabled": false,
+                "sizes": [0],
+                "ports": [22, 80],
+                "mode": [1],
+            })
+        );
+    }
+
+    #[test]
+    fn files_groups() {
+        let config = testing::MachineConfig::new(
+            testing::REPLACE,
+            &format!(
+                "{}netplan,Guest,network,/etc/netplan/50.yaml,Netplan config\n",
+                testing::FILES
+            ),
+            testing::TEMPLATES,
+        );
+        let skeleton = generate(&config.data().unwrap(), UserConfigFormat::Jsonc);
+        let value = user_config::parse(&skeleton, UserConfigFormat::Jsonc).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"network": {"files": {"netplan": ""}}})
+        );
+        assert!(
+            skeleton.contains("// Netplan config (mandatory)"),
+            "{}",
+            skeleton
+        );
+    }
+
+    #[test]
+    fn optional_groups() {
+        let data = testing::machine_data(concat!(
+            "gateway,user-data,false,true,network,Gateway,ip,,,,,\n",
+            "mac,user-data,true,true,nics.primary,MAC,mac,,,,,\n",
+        ));
+        let skeleton = generate(&data, UserConfigFormat::Jsonc);
+        assert!(
+            skeleton.contains("    // Group of config entries\n    \"network\": {"),
+            "{}",
+            skeleton
+        );
+        assert!(
+            skeleton.contains("    // Group of config entries (mandatory)\n    \"nics\": {"),
+            "{}",
+            skeleton
+        );
+    }
+}
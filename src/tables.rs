@@ -0,0 +1,675 @@
+//! Machine config tables: loading them out of their CSV files and relating them with each other.
+
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::types::{
+    self, ConfigEntry, ConfigPrimitives, Constraints, Files, FilesEntry, MachineData,
+    MachinegenError, ParsingError, Records, Replace, ReplaceEntry, TableError, TableLocation,
+    TableTypes, Tables, Template, TemplateEntry,
+};
+use crate::values;
+
+/// Loads the replace, files and templates tables found in `tables` and relates them
+/// into the machine data.
+pub fn process_relations(tables: &Path) -> Result<MachineData, MachinegenError> {
+    //
+    //      Load tables as their correct types
+    //
+    let replace_table = match load_table(tables, TableTypes::Replace) {
+        Ok(table) => {
+            let mut parsed_table: Vec<Replace> = Vec::new();
+            for record in table {
+                match record {
+                    Records::Replace(replace_record) => parsed_table.push(replace_record),
+                    _ => return Err(mislabeled(tables, TableTypes::Replace)),
+                }
+            }
+            parsed_table
+        }
+        Err(error) => return Err(error),
+    };
+    let files_table = match load_table(tables, TableTypes::Files) {
+        Ok(table) => {
+            let mut parsed_table: Vec<Files> = Vec::new();
+            for record in table {
+                match record {
+                    Records::Files(files_record) => parsed_table.push(files_record),
+                    _ => return Err(mislabeled(tables, TableTypes::Files)),
+                }
+            }
+            parsed_table
+        }
+        Err(error) => return Err(error),
+    };
+    let template_table = match load_table(tables, TableTypes::Template) {
+        Ok(table) => {
+            let mut parsed_table: Vec<Template> = Vec::new();
+            for record in table {
+                match record {
+                    Records::Template(template_record) => parsed_table.push(template_record),
+                    _ => return Err(mislabeled(tables, TableTypes::Template)),
+                }
+            }
+            parsed_table
+        }
+        Err(error) => return Err(error),
+    };
+
+    //
+    //      Define needed stuff to build the machine data fields
+    //
+
+    let mut config_entries: HashMap<String, ConfigEntry> = HashMap::new();
+
+    // Replacements of every template, by the dotted path of their key
+    let mut replacements: HashMap<String, HashMap<String, ReplaceEntry>> = HashMap::new();
+    let mut files: HashMap<String, FilesEntry> = HashMap::new();
+    let mut templates: HashMap<String, TemplateEntry> = HashMap::new();
+
+    //
+    //      Get both the user config tree keys and the replacements struct
+    //
+
+    for record in replace_table {
+        let entry = ReplaceEntry {
+            description: record.description,
+            mandatory: record.mandatory,
+            unique: record.unique,
+            template: record.template,
+            config_parent: record.config_parent,
+            value_type: record.value_type,
+            constraints: Constraints {
+                default: record.default,
+                min: record.min,
+                max: record.max,
+                pattern: record.pattern,
+                choices: record.choices,
+            },
+        };
+        if let Err(error) = check_constraints(&entry) {
+            return Err(relation_error(
+                tables,
+                TableTypes::Replace,
+                format!("constraints of key `{}` don't hold: {}", record.string, error),
+                "the default, min, max, pattern and choices columns must agree with each other and with the type column",
+            ));
+        }
+
+        let key = ConfigEntry {
+            children: None,
+            description: entry.description.clone(),
+            mandatory: entry.mandatory,
+            unique: entry.unique,
+            value: Some(entry.value_type.clone().unwrap_or(if entry.unique {
+                ConfigPrimitives::NoValue
+            } else {
+                ConfigPrimitives::Array
+            })),
+            constraints: entry.constraints.clone(),
+        };
+        // Placeholders and config paths use dots to reach into groups
+        if record.string.is_empty() || record.string.contains('.') {
+            return Err(relation_error(
+                tables,
+                TableTypes::Replace,
+                format!("key `{}` is not a valid key name", record.string),
+                "key names can't be empty or hold dots, dots separate the groups of a config path",
+            ));
+        }
+        config_group(&mut config_entries, &entry.config_parent)
+            .and_then(|group| insert_key(group, &record.string, key))
+            .map_err(|error| {
+                relation_error(
+                    tables,
+                    TableTypes::Replace,
+                    format!(
+                        "key `{}` can't go in group `{}`: {}",
+                        record.string, entry.config_parent, error
+                    ),
+                    GROUP_CAUSE,
+                )
+            })?;
+
+        // Keys of different groups may share a name, their paths tell them apart, and several
+        // templates may use the same key
+        replacements
+            .entry(entry.template.clone())
+            .or_default()
+            .insert(config_path(&entry.config_parent, &record.string), entry);
+    }
+
+    //
+    //     Get the files struct, along with the files groups of the user config tree
+    //
+
+    for record in files_table {
+        let file = ConfigEntry {
+            children: None,
+            description: record.description.clone(),
+            mandatory: true,
+            unique: true,
+            value: Some(ConfigPrimitives::NoValue),
+            constraints: Constraints::default(),
+        };
+        config_group(&mut config_entries, &record.config_parent)
+            .and_then(|group| {
+                let files_group = group.entry(String::from("files")).or_insert_with(|| {
+                    ConfigEntry {
+                        children: Some(HashMap::new()),
+                        description: String::from("Set of file paths to be copied from according to the machine data specs."),
+                        mandatory: false,
+                        unique: true,
+                        value: None,
+                        constraints: Constraints::default(),
+                    }
+                });
+                match &mut files_group.children {
+                    Some(children) => insert_key(children, &record.name, file),
+                    None => Err(String::from("`files` is already a key of this group")),
+                }
+            })
+            .map_err(|error| {
+                relation_error(
+                    tables,
+                    TableTypes::Files,
+                    format!(
+                        "file `{}` can't go in group `{}`: {}",
+                        record.name, record.config_parent, error
+                    ),
+                    GROUP_CAUSE,
+                )
+            })?;
+
+        files.insert(
+            config_path(&config_path(&record.config_parent, "files"), &record.name),
+            FilesEntry {
+                system: record.system,
+                target: record.target,
+                description: record.description,
+            },
+        );
+    }
+
+    settle_groups(&mut config_entries);
+
+    //
+    //      Get the templates struct
+    //
+
+    for record in template_table {
+        let template_entries = replacements.get(&record.name).cloned().unwrap_or_default();
+
+        templates.insert(
+            record.name.clone(),
+            TemplateEntry {
+                system: record.system,
+                source: record.source,
+                target: record.target,
+                description: record.description,
+                replacements: template_entries,
+            },
+        );
+    }
+
+    Ok(MachineData {
+        files,
+        templates,
+        config_keys: config_entries,
+    })
+}
+
+const GROUP_CAUSE: &str = "config_parent is either `root` or group names separated by dots, like `network.interfaces.primary`, and a name can't be both a key and a group";
+
+/// Dotted path of a key in the user config, `network.gateway` for the key `gateway` of the
+/// group `network` and just `hostname` for the key `hostname` of `root`.
+pub fn config_path(config_parent: &str, key: &str) -> String {
+    if config_parent == "root" {
+        key.to_string()
+    } else {
+        format!("{}.{}", config_parent, key)
+    }
+}
+
+/// Group names of a `config_parent`, from the outermost: none for `root`,
+/// `["network", "interfaces"]` for `network.interfaces`.
+pub fn parent_path(config_parent: &str) -> Result<Vec<&str>, String> {
+    if config_parent == "root" {
+        return Ok(Vec::new());
+    }
+    let names: Vec<&str> = config_parent.split('.').collect();
+    for name in &names {
+        match *name {
+            "" => return Err(String::from("group names can't be empty")),
+            "root" => return Err(String::from("`root` can only stand alone")),
+            "files" => return Err(String::from("`files` is reserved for the files groups")),
+            _ => {}
+        }
+    }
+    Ok(names)
+}
+
+/// Children of the group at `config_parent`, creating it and the groups holding it as needed.
+fn config_group<'a>(
+    entries: &'a mut HashMap<String, ConfigEntry>,
+    config_parent: &str,
+) -> Result<&'a mut HashMap<String, ConfigEntry>, String> {
+    let mut group = entries;
+    for name in parent_path(config_parent)? {
+        let entry = group
+            .entry(name.to_string())
+            .or_insert_with(|| ConfigEntry {
+                children: Some(HashMap::new()),
+                description: String::from("Group of config entries"),
+                mandatory: false,
+                unique: true,
+                value: None,
+                constraints: Constraints::default(),
+            });
+        group = match &mut entry.children {
+            Some(children) => children,
+            None => return Err(format!("`{}` is already a key", name)),
+        };
+    }
+    Ok(group)
+}
+
+/// Makes the groups mandatory as long as one of their children has to be given, so groups
+/// of optional or defaulted keys can be left out of the user config. Returns whether
+/// `entries` hold such a child.
+fn settle_groups(entries: &mut HashMap<String, ConfigEntry>) -> bool {
+    let mut required = false;
+    for entry in entries.values_mut() {
+        if let Some(children) = &mut entry.children {
+            entry.mandatory = settle_groups(children);
+        }
+        required |= entry.mandatory && entry.constraints.default.is_none();
+    }
+    required
+}
+
+/// Adds a key to a group. Keys declared twice keep their last declaration, like the
+/// replacements do, but a key can't take the place of a group.
+fn insert_key(
+    group: &mut HashMap<String, ConfigEntry>,
+    name: &str,
+    entry: ConfigEntry,
+) -> Result<(), String> {
+    if group
+        .get(name)
+        .map(|existing| existing.children.is_some())
+        .unwrap_or(false)
+    {
+        return Err(format!("`{}` is already a group", name));
+    }
+    group.insert(name.to_string(), entry);
+    Ok(())
+}
+
+/// Checks the default and constraints of a replacement agree with each other and its type,
+/// so a bad default is blamed on the tables rather than on the user configs leaving it out.
+fn check_constraints(entry: &ReplaceEntry) -> Result<(), String> {
+    let constraints = &entry.constraints;
+    if let Some(pattern) = &constraints.pattern {
+        Regex::new(pattern).map_err(|error| format!("invalid pattern: {}", error))?;
+    }
+    if let (Some(min), Some(max)) = (constraints.min, constraints.max) {
+        if min > max {
+            return Err(format!("min {} is greater than max {}", min, max));
+        }
+    }
+
+    let primitive = entry.value_type.clone().unwrap_or(if entry.unique {
+        ConfigPrimitives::NoValue
+    } else {
+        ConfigPrimitives::Array
+    });
+    let items = match values::default_value(&primitive, constraints, entry.unique) {
+        Some(Value::Array(items)) => items,
+        Some(value) => vec![value],
+        None => Vec::new(),
+    };
+    for item in &items {
+        values::check(&primitive, constraints, item)
+            .map_err(|error| format!("invalid default: {}", error))?;
+    }
+    Ok(())
+}
+
+/// Location of a table inside the `tables` folder.
+pub fn table_path(tables: &Path, table_type: &TableTypes) -> PathBuf {
+    let mut path = tables.join(table_type.name());
+    path.set_extension("csv");
+    path
+}
+
+/// A table problem found while relating the tables with each other.
+fn relation_error(
+    tables: &Path,
+    table_type: TableTypes,
+    message: String,
+    cause: &str,
+) -> MachinegenError {
+    MachinegenError::Table {
+        path: table_path(tables, &table_type),
+        error: TableError::Parsing(vec![ParsingError {
+            message,
+            cause: cause.to_string(),
+            location: None,
+        }]),
+    }
+}
+
+pub fn mislabeled(tables: &Path, table_type: TableTypes) -> MachinegenError {
+    let name = table_type.name();
+    relation_error(
+        tables,
+        table_type,
+        format!("records are not {} records", name),
+        "the table appears to be mislabeled as such",
+    )
+}
+
+/// Loads a table out of the `tables` folder, as the records of its type.
+pub fn load_table(tables: &Path, table_type: TableTypes) -> Result<Tables, MachinegenError> {
+    let path = table_path(tables, &table_type);
+
+    let content = fs::read_to_string(&path).map_err(|error| MachinegenError::Table {
+        path: path.clone(),
+        error: TableError::Io(error),
+    })?;
+
+    let table = match table_type {
+        TableTypes::Files => parse_records(&path, &content, Records::Files),
+        TableTypes::Replace => parse_records(&path, &content, Records::Replace),
+        TableTypes::Template => parse_records(&path, &content, Records::Template),
+        TableTypes::Dependencies => parse_records(&path, &content, Records::Dependency),
+        TableTypes::Images => parse_records(&path, &content, Records::Image),
+    }
+    .map_err(|error| MachinegenError::Table {
+        path: path.clone(),
+        error,
+    })?;
+
+    Ok(table)
+}
+
+/// Deserializes every row of a table, collecting the problems of all bad rows before failing.
+fn parse_records<T: DeserializeOwned>(
+    path: &Path,
+    content: &str,
+    wrap: fn(T) -> Records,
+) -> Result<Tables, TableError> {
+    let file = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let lines: Vec<&str> = content.lines().collect();
+
+    let mut reader = csv::Reader::from_reader(content.as_bytes());
+    let headers = reader.headers().map_err(TableError::Csv)?.clone();
+
+    let mut table: Tables = Vec::new();
+    let mut errors: Vec<ParsingError> = Vec::new();
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(error) => match error.kind() {
+                csv::ErrorKind::UnequalLengths {
+                    pos: Some(position),
+                    expected_len,
+                    len,
+                } => {
+                    errors.push(row_error(
+                        &file,
+                        &lines,
+                        position.line(),
+                        None,
+                        format!("expected {} fields, found {}", expected_len, len),
+                    ));
+                    continue;
+                }
+                _ => return Err(TableError::Csv(error)),
+            },
+        };
+
+        match record.deserialize::<T>(Some(&headers)) {
+            Ok(parsed) => table.push(wrap(parsed)),
+            Err(error) => {
+                let line = record
+                    .position()
+                    .map(|position| position.line())
+                    .unwrap_or(0);
+                let (field, message) = match error.kind() {
+                    csv::ErrorKind::Deserialize { err, .. } => {
+                        let rejected = match err.kind() {
+                            csv::DeserializeErrorKind::Message(message) => {
+                                types::rejected_column(message)
+                            }
+                            _ => None,
+                        };
+                        match rejected {
+                            Some((column, message)) => {
+                                let field = headers.iter().position(|header| header == column);
+                                let value = field.and_then(|field| record.get(field)).unwrap_or("");
+                                (field, message_expectation(message, value))
+                            }
+                            None => {
+                                let field = err.field().map(|field| field as usize);
+                                let value = field.and_then(|field| record.get(field)).unwrap_or("");
+                                (field, expectation(err.kind(), value))
+                            }
+                        }
+                    }
+                    kind => (None, format!("{:?}", kind)),
+                };
+                errors.push(row_error(
+                    &file,
+                    &lines,
+                    line,
+                    field.map(|field| (field, headers.get(field).unwrap_or("?"))),
+                    message,
+                ));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(table)
+    } else {
+        Err(TableError::Parsing(errors))
+    }
+}
+
+fn row_error(
+    file: &str,
+    lines: &[&str],
+    line: u64,
+    field: Option<(usize, &str)>,
+    message: String,
+) -> ParsingError {
+    let snippet = lines
+        .get((line as usize).saturating_sub(1))
+        .copied()
+        .unwrap_or("");
+    ParsingError {
+        message,
+        cause: String::new(),
+        location: Some(TableLocation {
+            file: file.to_string(),
+            line,
+            column: field.map(|(_, header)| header.to_string()),
+            snippet: snippet.to_string(),
+            span: field.and_then(|(index, _)| field_span(snippet, index)),
+        }),
+    }
+}
+
+/// Describes what a column expected, e.g. "expected Guest|Host, got `guest `".
+fn expectation(kind: &csv::DeserializeErrorKind, value: &str) -> String {
+    match kind {
+        csv::DeserializeErrorKind::ParseBool(_) => {
+            format!("expected true|false, got `{}`", value)
+        }
+        csv::DeserializeErrorKind::ParseInt(_) => {
+            format!("expected an integer, got `{}`", value)
+        }
+        csv::DeserializeErrorKind::ParseFloat(_) => {
+            format!("expected a number, got `{}`", value)
+        }
+        csv::DeserializeErrorKind::UnexpectedEndOfRow => String::from("missing value"),
+        csv::DeserializeErrorKind::Message(message) => message_expectation(message, value),
+        kind => format!("{}, got `{}`", kind, value),
+    }
+}
+
+/// Same as `expectation`, for the errors serde only has a message for.
+fn message_expectation(message: &str, value: &str) -> String {
+    if message.starts_with("unknown variant") {
+        // serde lists the variants as "expected `A` or `B`" or "expected one of `A`, `B`, `C`"
+        let expected: Vec<&str> = message
+            .split_once("expected")
+            .map(|(_, expected)| expected.split('`').skip(1).step_by(2).collect())
+            .unwrap_or_default();
+        format!("expected {}, got `{}`", expected.join("|"), value)
+    } else {
+        format!("{}, got `{}`", message, value)
+    }
+}
+
+/// Byte range of the `index`th field in a raw CSV line, quotes included.
+fn field_span(line: &str, index: usize) -> Option<(usize, usize)> {
+    let mut field = 0;
+    let mut start = 0;
+    let mut quoted = false;
+
+    for (offset, character) in line.char_indices() {
+        match character {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                if field == index {
+                    return Some((start, offset - start));
+                }
+                field += 1;
+                start = offset + 1;
+            }
+            _ => {}
+        }
+    }
+    if field == index {
+        Some((start, line.len() - start))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "name,system,source,target,description\n";
+
+    fn parse_templates(rows: &str) -> Result<Tables, TableError> {
+        parse_records::<Template>(
+            Path::new("tables/templates.csv"),
+            &format!("{}{}", HEADER, rows),
+            Records::Template,
+        )
+    }
+
+    fn parsing_errors(rows: &str) -> Vec<ParsingError> {
+        match parse_templates(rows) {
+            Err(TableError::Parsing(errors)) => errors,
+            other => panic!("expected parsing errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn spans() {
+        let line = "a,bc,,\"d,e\",f";
+        assert_eq!(field_span(line, 0), Some((0, 1)));
+        assert_eq!(field_span(line, 1), Some((2, 2)));
+        assert_eq!(field_span(line, 2), Some((5, 0)));
+        assert_eq!(field_span(line, 3), Some((6, 5)));
+        assert_eq!(field_span(line, 4), Some((12, 1)));
+        assert_eq!(field_span(line, 5), None);
+        assert_eq!(field_span("", 0), Some((0, 0)));
+        assert_eq!(field_span("ñ,b", 1), Some((3, 1)));
+    }
+
+    #[test]
+    fn records() {
+        let table =
+            parse_templates("user-data,Guest,templates/user-data,user-data,Cloud-init\n").unwrap();
+        assert_eq!(table.len(), 1);
+        match &table[0] {
+            Records::Template(template) => {
+                assert_eq!(template.name, "user-data");
+                assert_eq!(template.target, PathBuf::from("user-data"));
+            }
+            other => panic!("expected a template, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn errors_of_every_row() {
+        let errors = parsing_errors(concat!(
+            "user-data,Guest,templates/user-data,user-data,Cloud-init\n",
+            "main.tf,guest,templates/main.tf,main.tf,Terraform\n",
+            "meta-data,Guest,templates/meta-data\n",
+            "network,Host ,templates/network,network,Network\n",
+        ));
+        assert_eq!(errors.len(), 3);
+
+        let location = errors[0].location.as_ref().unwrap();
+        assert_eq!(errors[0].message, "expected Guest|Host, got `guest`");
+        assert_eq!(location.file, "templates.csv");
+        assert_eq!(location.line, 3);
+        assert_eq!(location.column.as_deref(), Some("system"));
+        assert_eq!(location.span, Some((8, 5)));
+
+        let location = errors[1].location.as_ref().unwrap();
+        assert_eq!(errors[1].message, "expected 5 fields, found 3");
+        assert_eq!(location.line, 4);
+        assert_eq!(location.snippet, "meta-data,Guest,templates/meta-data");
+        assert_eq!(location.span, None);
+
+        let location = errors[2].location.as_ref().unwrap();
+        assert_eq!(errors[2].message, "expected Guest|Host, got `Host `");
+        assert_eq!(location.line, 5);
+        assert_eq!(location.span, Some((8, 5)));
+    }
+
+    #[test]
+    fn rejected_columns() {
+        // The caret goes to the column that rejected the value, not to the first one holding it
+        let errors = parsing_errors("guest,guest,templates/guest,guest,guest\n");
+        let location = errors[0].location.as_ref().unwrap();
+        assert_eq!(errors[0].message, "expected Guest|Host, got `guest`");
+        assert_eq!(location.column.as_deref(), Some("system"));
+        assert_eq!(location.span, Some((6, 5)));
+
+        let errors = match parse_records::<Replace>(
+            Path::new("tables/replace.csv"),
+            "string,template,mandatory,unique,config_parent,description,type\n\
+            u32,user-data,true,true,root,u32,u32\n",
+            Records::Replace,
+        ) {
+            Err(TableError::Parsing(errors)) => errors,
+            other => panic!("expected parsing errors, got {:?}", other),
+        };
+        let location = errors[0].location.as_ref().unwrap();
+        assert!(
+            errors[0].message.ends_with("got `u32`"),
+            "{}",
+            errors[0].message
+        );
+        assert_eq!(location.column.as_deref(), Some("type"));
+        assert_eq!(location.span, Some((33, 3)));
+    }
+}
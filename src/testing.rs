@@ -0,0 +1,74 @@
+//! Throwaway machine config folders for the unit tests.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs, process};
+
+use crate::tables;
+use crate::types::{MachineData, MachinegenError};
+
+/// Header of replace tables using every optional column.
+pub const REPLACE: &str =
+    "string,template,mandatory,unique,config_parent,description,type,default,min,max,pattern,choices\n";
+pub const FILES: &str = "name,system,config_parent,target,description\n";
+pub const TEMPLATES: &str = "name,system,source,target,description\n";
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// Machine config folder holding the `tables` folder and template sources, removed once dropped.
+pub struct MachineConfig {
+    pub dir: PathBuf,
+}
+
+impl MachineConfig {
+    /// A machine config with the given replace, files and templates tables, headers included.
+    pub fn new(replace: &str, files: &str, templates: &str) -> MachineConfig {
+        let dir = env::temp_dir().join(format!(
+            "machinegen-unit-{}-{}",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        let config = MachineConfig { dir };
+        config.write("tables/replace.csv", replace);
+        config.write("tables/files.csv", files);
+        config.write("tables/templates.csv", templates);
+        config
+    }
+
+    /// Writes `content` at `relative`, creating the folders leading to it.
+    pub fn write(&self, relative: &str, content: &str) -> PathBuf {
+        let path = self.dir.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    pub fn tables(&self) -> PathBuf {
+        self.dir.join("tables")
+    }
+
+    pub fn data(&self) -> Result<MachineData, MachinegenError> {
+        tables::process_relations(&self.tables())
+    }
+}
+
+impl Drop for MachineConfig {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Machine data out of a replace table alone, every key going to the `user-data` template.
+pub fn machine_data(replace_rows: &str) -> MachineData {
+    MachineConfig::new(
+        &format!("{}{}", REPLACE, replace_rows),
+        FILES,
+        &format!(
+            "{}user-data,Guest,templates/user-data,user-data,User data\n",
+            TEMPLATES
+        ),
+    )
+    .data()
+    .unwrap()
+}
@@ -1,10 +1,11 @@
-use csv;
-use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Replace {
     pub string: String,
     pub template: String,
@@ -12,18 +13,107 @@ pub struct Replace {
     pub unique: bool,
     pub config_parent: String,
     pub description: String,
+    /// Optional `type` column, untyped values are taken as they come.
+    #[serde(rename = "type", default, deserialize_with = "value_type")]
+    pub value_type: Option<ConfigPrimitives>,
+    /// Optional `default` column, used when the user config leaves the key out.
+    /// List defaults separate their items with `|`.
+    #[serde(default)]
+    pub default: Option<String>,
+    /// Optional `min` and `max` columns, bounding numbers and sizes, or the length of text.
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    /// Optional `pattern` column, a regular expression values must match.
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// Optional `choices` column, the values allowed separated by `|`.
+    #[serde(default, deserialize_with = "choices")]
+    pub choices: Option<Vec<String>>,
+}
+
+fn value_type<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ConfigPrimitives>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    if name.trim().is_empty() {
+        return Ok(None);
+    }
+    ConfigPrimitives::from_name(name.trim())
+        .map(Some)
+        .ok_or_else(|| column_error("type", de::Error::unknown_variant(&name, VALUE_TYPES)))
+}
+
+fn system<'de, D: Deserializer<'de>>(deserializer: D) -> Result<System, D::Error> {
+    System::deserialize(deserializer).map_err(|error| column_error("system", error))
+}
+
+fn image_format<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ImageFormat, D::Error> {
+    ImageFormat::deserialize(deserializer).map_err(|error| column_error("format", error))
+}
+
+/// Names the column of an error csv can't locate by itself, like an unknown enum variant,
+/// as `column `system`: unknown variant ...`.
+fn column_error<E: de::Error>(column: &str, error: E) -> E {
+    E::custom(format!("column `{}`: {}", column, error))
+}
+
+/// Column and message of an error named by `column_error`.
+pub fn rejected_column(message: &str) -> Option<(&str, &str)> {
+    message.strip_prefix("column `")?.split_once("`: ")
+}
+
+fn choices<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<String>>, D::Error> {
+    let choices = String::deserialize(deserializer)?;
+    if choices.trim().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(
+        choices
+            .split('|')
+            .map(|choice| choice.trim().to_string())
+            .collect(),
+    ))
 }
 
-#[derive(Debug, Clone)]
+/// Names accepted in the `type` column, as listed in table errors.
+const VALUE_TYPES: &[&str] = &[
+    "string",
+    "int",
+    "uint",
+    "float",
+    "bool",
+    "path",
+    "ip",
+    "cidr",
+    "mac",
+    "size",
+    "enum(a|b|...)",
+];
+
+#[derive(Serialize, Debug, Clone)]
 pub struct ReplaceEntry {
     pub template: String,
     pub mandatory: bool,
     pub unique: bool,
     pub config_parent: String,
     pub description: String,
+    pub value_type: Option<ConfigPrimitives>,
+    pub constraints: Constraints,
+}
+
+/// Default value and constraints of a config key, out of the optional replace table columns.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Constraints {
+    pub default: Option<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub pattern: Option<String>,
+    pub choices: Option<Vec<String>>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub enum System {
     Guest,
     Host,
@@ -38,51 +128,357 @@ impl System {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Template {
     pub name: String,
+    #[serde(deserialize_with = "system")]
     pub system: System,
     pub source: PathBuf,
     pub target: PathBuf,
     pub description: String,
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Debug)]
 pub struct TemplateEntry {
     pub system: System,
     pub source: PathBuf,
     pub target: PathBuf,
     pub description: String,
+    /// Replacements of the template, by the dotted path of their key in the user config.
     pub replacements: HashMap<String, ReplaceEntry>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Files {
     pub name: String,
+    #[serde(deserialize_with = "system")]
     pub system: System,
     pub config_parent: String,
     pub target: PathBuf,
     pub description: String,
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Debug)]
 pub struct FilesEntry {
     pub system: System,
     pub target: PathBuf,
     pub description: String,
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Dependency {
+    pub name: String,
+    pub version: String,
+    pub url: String,
+    pub sha256: String,
+    pub target: PathBuf,
+    pub executable: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Qcow2,
+    Raw,
+    Img,
+}
+
+impl ImageFormat {
+    pub fn value(&self) -> &'static str {
+        match self {
+            ImageFormat::Qcow2 => "qcow2",
+            ImageFormat::Raw => "raw",
+            ImageFormat::Img => "img",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<ImageFormat> {
+        match extension.to_lowercase().as_str() {
+            "qcow2" => Some(ImageFormat::Qcow2),
+            "raw" => Some(ImageFormat::Raw),
+            "img" => Some(ImageFormat::Img),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Image {
+    pub name: String,
+    pub distro: String,
+    pub release: String,
+    pub arch: String,
+    pub url: String,
+    pub sha256: String,
+    #[serde(deserialize_with = "image_format")]
+    pub format: ImageFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UserConfigFormat {
+    Json,
+    Jsonc,
+    Json5,
+}
+
+impl UserConfigFormat {
+    pub const ALL: [UserConfigFormat; 3] = [
+        UserConfigFormat::Json,
+        UserConfigFormat::Jsonc,
+        UserConfigFormat::Json5,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            UserConfigFormat::Json => "json",
+            UserConfigFormat::Jsonc => "jsonc",
+            UserConfigFormat::Json5 => "json5",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<UserConfigFormat> {
+        UserConfigFormat::ALL
+            .into_iter()
+            .find(|format| format.extension() == extension.to_lowercase())
+    }
+}
+
 #[derive(Debug)]
 pub enum TableError {
     Io(io::Error),
     Csv(csv::Error),
-    Parsing(ParsingError),
+    /// Every problem found in the table, rows that can't be read included.
+    Parsing(Vec<ParsingError>),
+}
+
+impl error::Error for TableError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            TableError::Io(error) => Some(error),
+            TableError::Csv(error) => Some(error),
+            TableError::Parsing(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TableError::Io(error) => write!(formatter, "could not read the table: {}", error),
+            TableError::Csv(error) => write!(formatter, "{}", error),
+            TableError::Parsing(errors) => {
+                for (index, error) in errors.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(formatter)?;
+                    }
+                    write!(formatter, "{}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ParsingError {
     pub message: String,
     pub cause: String,
+    /// Where in the table file the problem is, when it's about a single row.
+    pub location: Option<TableLocation>,
+}
+
+impl fmt::Display for ParsingError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let location = match &self.location {
+            Some(location) => location,
+            None if self.cause.is_empty() => return write!(formatter, "{}", self.message),
+            None => return write!(formatter, "{}: {}", self.message, self.cause),
+        };
+
+        write!(formatter, "{}:{}", location.file, location.line)?;
+        if let Some(column) = &location.column {
+            write!(formatter, " column `{}`", column)?;
+        }
+        write!(formatter, ": {}", self.message)?;
+
+        // Caret snippet of the offending line, underlining the value when it's known
+        let gutter = location.line.to_string().len();
+        write!(
+            formatter,
+            "\n{:>width$} |\n{} | {}",
+            "",
+            location.line,
+            location.snippet,
+            width = gutter
+        )?;
+        if let Some((start, length)) = location.span {
+            let padding: String = location.snippet[..start]
+                .chars()
+                .map(|character| if character == '\t' { '\t' } else { ' ' })
+                .collect();
+            write!(
+                formatter,
+                "\n{:>width$} | {}{}",
+                "",
+                padding,
+                "^".repeat(length.max(1)),
+                width = gutter
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A spot in a table file, with the line it's in for display.
+#[derive(Debug)]
+pub struct TableLocation {
+    pub file: String,
+    pub line: u64,
+    pub column: Option<String>,
+    pub snippet: String,
+    /// Byte offset and length of the offending value in `snippet`.
+    pub span: Option<(usize, usize)>,
+}
+
+/// Every way a machinegen command can fail, each category exiting with its own code.
+#[derive(Debug)]
+pub enum MachinegenError {
+    /// A machine config table can't be read, or its contents make no sense.
+    Table {
+        path: PathBuf,
+        error: TableError,
+    },
+    /// The user or machine config can't be read or used as is.
+    Config(String),
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// An external program (git, terraform) failed.
+    Subprocess {
+        command: String,
+        message: String,
+    },
+    Download {
+        url: String,
+        message: String,
+    },
+    /// The user config doesn't fit the machine config.
+    Validation(Vec<ValidationError>),
+    /// The workspace is not in a state that allows the command, like deploying without a plan.
+    Precondition(String),
+    /// The rendered files can't be packed into the seed image.
+    Image(String),
+    /// Machinegen's own output (state records, schemas, debug dumps) can't be written as JSON.
+    Serialization(serde_json::Error),
+}
+
+impl MachinegenError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MachinegenError::Table { .. } => 3,
+            MachinegenError::Config(_) => 4,
+            MachinegenError::Validation(_) => 5,
+            MachinegenError::Io { .. } => 6,
+            MachinegenError::Subprocess { .. } => 7,
+            MachinegenError::Download { .. } => 8,
+            MachinegenError::Precondition(_) => 9,
+            MachinegenError::Image(_) => 10,
+            MachinegenError::Serialization(_) => 11,
+        }
+    }
+
+    pub fn io(path: &Path, error: io::Error) -> MachinegenError {
+        MachinegenError::Io {
+            path: path.to_path_buf(),
+            error,
+        }
+    }
+
+    pub fn subprocess(command: &str, message: &str) -> MachinegenError {
+        MachinegenError::Subprocess {
+            command: command.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for MachinegenError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MachinegenError::Table {
+                path,
+                error: error @ TableError::Parsing(errors),
+            } => match errors.as_slice() {
+                [single] if single.location.is_some() => write!(formatter, "{}", single),
+                [_] => write!(formatter, "{}: {}", path.display(), error),
+                _ => write!(
+                    formatter,
+                    "{} has {} problems:\n{}",
+                    path.display(),
+                    errors.len(),
+                    error
+                ),
+            },
+            MachinegenError::Table { path, error } => {
+                write!(formatter, "{}: {}", path.display(), error)
+            }
+            MachinegenError::Config(message) => write!(formatter, "{}", message),
+            MachinegenError::Io { path, error } => {
+                write!(formatter, "{}: {}", path.display(), error)
+            }
+            MachinegenError::Subprocess { command, message } => {
+                write!(formatter, "`{}` failed: {}", command, message)
+            }
+            MachinegenError::Download { url, message } => {
+                write!(formatter, "Could not download {}: {}", url, message)
+            }
+            MachinegenError::Validation(errors) => {
+                write!(
+                    formatter,
+                    "User config has {} problem{}, fix {} and try again.",
+                    errors.len(),
+                    if errors.len() == 1 { "" } else { "s" },
+                    if errors.len() == 1 { "it" } else { "them" }
+                )?;
+                for error in errors {
+                    write!(formatter, "\n  {}", error)?;
+                }
+                Ok(())
+            }
+            MachinegenError::Precondition(message) => write!(formatter, "{}", message),
+            MachinegenError::Image(message) => {
+                write!(formatter, "Could not build the seed image: {}", message)
+            }
+            MachinegenError::Serialization(error) => {
+                write!(formatter, "Could not write JSON: {}", error)
+            }
+        }
+    }
+}
+
+impl error::Error for MachinegenError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            // The table error says where, what went wrong underneath is its own source
+            MachinegenError::Table { error, .. } => error.source(),
+            MachinegenError::Io { error, .. } => Some(error),
+            MachinegenError::Serialization(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ValidationError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}: {}", self.path, self.message)
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -90,12 +486,17 @@ pub enum Records {
     Replace(Replace),
     Template(Template),
     Files(Files),
+    Dependency(Dependency),
+    Image(Image),
 }
 
+#[derive(Clone)]
 pub enum TableTypes {
     Replace,
     Template,
     Files,
+    Dependencies,
+    Images,
 }
 
 pub type Tables = Vec<Records>;
@@ -106,39 +507,100 @@ impl TableTypes {
             TableTypes::Files => "files",
             TableTypes::Replace => "replace",
             TableTypes::Template => "templates",
+            TableTypes::Dependencies => "dependencies",
+            TableTypes::Images => "images",
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Debug)]
 pub struct ConfigEntry {
     pub children: Option<HashMap<String, ConfigEntry>>, // if children is Some then value should be None
     pub description: String,
     pub mandatory: bool,
     pub unique: bool,
     pub value: Option<ConfigPrimitives>, // if value is Some then children should be None
+    pub constraints: Constraints,
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Debug, Clone, PartialEq)]
 pub enum ConfigPrimitives {
     String,
-    i32,
-    i64,
-    u32,
-    u64,
-    f32,
-    f64,
-    bool,
+    I32,
+    I64,
+    U32,
+    U64,
+    F32,
+    F64,
+    Bool,
     NoValue,
     Array,
+    Path,
+    Ip,
+    Cidr,
+    Mac,
+    Size,
+    Enum(Vec<String>),
+}
+
+impl ConfigPrimitives {
+    /// Type named in the `type` column of the replace table, e.g. `uint` or `enum(a|b)`.
+    pub fn from_name(name: &str) -> Option<ConfigPrimitives> {
+        if let Some(choices) = name
+            .strip_prefix("enum(")
+            .and_then(|choices| choices.strip_suffix(')'))
+        {
+            let choices: Vec<String> = choices
+                .split('|')
+                .map(|choice| choice.trim().to_string())
+                .collect();
+            if choices.iter().any(String::is_empty) {
+                return None;
+            }
+            return Some(ConfigPrimitives::Enum(choices));
+        }
+
+        match name {
+            "string" => Some(ConfigPrimitives::String),
+            "int" => Some(ConfigPrimitives::I64),
+            "uint" => Some(ConfigPrimitives::U64),
+            "float" => Some(ConfigPrimitives::F64),
+            "bool" => Some(ConfigPrimitives::Bool),
+            "path" => Some(ConfigPrimitives::Path),
+            "ip" => Some(ConfigPrimitives::Ip),
+            "cidr" => Some(ConfigPrimitives::Cidr),
+            "mac" => Some(ConfigPrimitives::Mac),
+            "size" => Some(ConfigPrimitives::Size),
+            _ => None,
+        }
+    }
+
+    /// What a value of this type looks like, for messages.
+    pub fn describe(&self) -> String {
+        String::from(match self {
+            ConfigPrimitives::String => "a string",
+            ConfigPrimitives::I32 | ConfigPrimitives::I64 => "an integer",
+            ConfigPrimitives::U32 | ConfigPrimitives::U64 => "a non-negative integer",
+            ConfigPrimitives::F32 | ConfigPrimitives::F64 => "a number",
+            ConfigPrimitives::Bool => "true or false",
+            ConfigPrimitives::NoValue | ConfigPrimitives::Array => "a single value",
+            ConfigPrimitives::Path => "a path",
+            ConfigPrimitives::Ip => "an IP address",
+            ConfigPrimitives::Cidr => "a network in CIDR notation, like 10.0.0.0/24",
+            ConfigPrimitives::Mac => "a MAC address, like 52:54:00:12:34:56",
+            ConfigPrimitives::Size => "a size, like 20G or a number of bytes",
+            ConfigPrimitives::Enum(choices) => return format!("one of {}", choices.join("|")),
+        })
+    }
 }
 
 pub type NoValue = String;
 pub type Array = Vec<ConfigPrimitives>;
 
-#[derive(Debug)]
+#[derive(Serialize, Debug)]
 pub struct MachineData {
     pub config_keys: HashMap<String, ConfigEntry>,
     pub templates: HashMap<String, TemplateEntry>,
+    /// Files, by their dotted path in the user config, like `network.files.netplan`.
     pub files: HashMap<String, FilesEntry>,
 }
@@ -0,0 +1,245 @@
+//! The user config: the values a user picks for the keys declared by the machine config tables.
+
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::types::{MachinegenError, UserConfigFormat};
+
+/// Location and format of the user config stored in the `config` folder, if there is one.
+pub fn find(config: &Path) -> Option<(PathBuf, UserConfigFormat)> {
+    UserConfigFormat::ALL.into_iter().find_map(|format| {
+        let mut path = config.join("user");
+        path.set_extension(format.extension());
+        if path.is_file() {
+            Some((path, format))
+        } else {
+            None
+        }
+    })
+}
+
+/// Reads the user config stored in the `config` folder.
+pub fn load(config: &Path) -> Result<Value, MachinegenError> {
+    let (path, format) = find(config).ok_or_else(|| {
+        MachinegenError::Config(format!(
+            "Could not read the user config: there is none in {} (user.json, user.jsonc or user.json5)",
+            config.display()
+        ))
+    })?;
+
+    let content = fs::read_to_string(&path).map_err(|error| MachinegenError::io(&path, error))?;
+    parse(&content, format).map_err(|error| {
+        MachinegenError::Config(format!(
+            "Could not read the user config: {}:{}",
+            path.display(),
+            error
+        ))
+    })
+}
+
+/// Parses user config content, reporting errors as `line:column: message`.
+pub fn parse(content: &str, format: UserConfigFormat) -> Result<Value, String> {
+    match format {
+        UserConfigFormat::Json => serde_json::from_str(content).map_err(|error| {
+            format!(
+                "{}:{}: {}",
+                error.line(),
+                error.column(),
+                error
+                    .to_string()
+                    .split(" at line")
+                    .next()
+                    .unwrap_or_default()
+            )
+        }),
+        UserConfigFormat::Jsonc => {
+            serde_json::from_str(&strip_json_comments(content)).map_err(|error| {
+                format!(
+                    "{}:{}: {}",
+                    error.line(),
+                    error.column(),
+                    error
+                        .to_string()
+                        .split(" at line")
+                        .next()
+                        .unwrap_or_default()
+                )
+            })
+        }
+        UserConfigFormat::Json5 => json5::from_str(content).map_err(|error| match error {
+            json5::Error::Message { msg, location } => {
+                // Pest errors span several lines, the last one says what was expected
+                let message = msg
+                    .lines()
+                    .last()
+                    .map(|line| line.trim_start_matches([' ', '=']).to_string())
+                    .unwrap_or_default();
+                match location {
+                    Some(location) => format!("{}:{}: {}", location.line, location.column, message),
+                    None => message,
+                }
+            }
+        }),
+    }
+}
+
+/// Guesses the format of user config content, picking the strictest one that parses.
+pub fn detect_format(content: &str) -> Result<UserConfigFormat, String> {
+    for format in UserConfigFormat::ALL {
+        if parse(content, format).is_ok() {
+            return Ok(format);
+        }
+    }
+    // JSON5 accepts all of the others, so its error is the most meaningful one
+    match parse(content, UserConfigFormat::Json5) {
+        Ok(_) => Ok(UserConfigFormat::Json5),
+        Err(error) => Err(error),
+    }
+}
+
+/// Blanks out `//` and `/* */` comments outside of strings, keeping line and column positions.
+pub fn strip_json_comments(content: &str) -> String {
+    let mut stripped = String::with_capacity(content.len());
+    let mut chars = content.chars().peekable();
+    let mut in_string = false;
+
+    while let Some(current) = chars.next() {
+        if in_string {
+            stripped.push(current);
+            if current == '\\' {
+                if let Some(escaped) = chars.next() {
+                    stripped.push(escaped);
+                }
+            } else if current == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        match (current, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                stripped.push(current);
+            }
+            ('/', Some('/')) => {
+                while let Some(&next) = chars.peek() {
+                    if next == '\n' {
+                        break;
+                    }
+                    stripped.push(' ');
+                    chars.next();
+                }
+                stripped.push(' ');
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                stripped.push_str("  ");
+                let mut previous = ' ';
+                for next in chars.by_ref() {
+                    stripped.push(if next == '\n' { '\n' } else { ' ' });
+                    if previous == '*' && next == '/' {
+                        break;
+                    }
+                    previous = next;
+                }
+            }
+            _ => stripped.push(current),
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_comments() {
+        assert_eq!(
+            strip_json_comments("{\"a\": 1, // one\n\"b\": 2}"),
+            "{\"a\": 1,       \n\"b\": 2}"
+        );
+        assert_eq!(strip_json_comments("{} // end"), "{}       ");
+    }
+
+    #[test]
+    fn block_comments() {
+        assert_eq!(
+            strip_json_comments("{/* a\nb */\"a\": 1}"),
+            "{    \n    \"a\": 1}"
+        );
+        assert_eq!(strip_json_comments("1 /**/"), "1     ");
+        // Unclosed comments run to the end of the content
+        assert_eq!(strip_json_comments("1 /* a"), "1     ");
+    }
+
+    #[test]
+    fn comments_in_strings() {
+        let content = r#"{"url": "http://host/*path*/", "quote": "\"//", "end": "\\"} // x"#;
+        let stripped = strip_json_comments(content);
+        assert_eq!(stripped.trim_end(), &content[..content.len() - 5]);
+        assert_eq!(stripped.len(), content.len());
+    }
+
+    #[test]
+    fn positions_kept() {
+        let content = "{\n  // first\n  \"a\": [1, /* two */ 2,],\n}";
+        let error = parse(content, UserConfigFormat::Jsonc).unwrap_err();
+        assert!(error.starts_with("3:"), "{}", error);
+        let value = parse(
+            "{\n  // first\n  \"a\": [1, /* two */ 2]\n}",
+            UserConfigFormat::Jsonc,
+        )
+        .unwrap();
+        assert_eq!(value["a"][1], 2);
+    }
+
+    #[test]
+    fn detected_formats() {
+        assert_eq!(
+            detect_format(r#"{"a": [1, 2]}"#),
+            Ok(UserConfigFormat::Json)
+        );
+        assert_eq!(
+            detect_format("{\"a\": 1 // one\n}"),
+            Ok(UserConfigFormat::Jsonc)
+        );
+        assert_eq!(
+            detect_format("{a: 'one', b: [1, 2,],}"),
+            Ok(UserConfigFormat::Json5)
+        );
+        let error = detect_format("{\n  a: \n}").unwrap_err();
+        assert!(error.starts_with("3:"), "{}", error);
+    }
+
+    #[test]
+    fn loaded_file() {
+        let folder =
+            std::env::temp_dir().join(format!("machinegen-user-config-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let missing = load(&folder);
+        fs::write(folder.join("user.json5"), "{hostname: 'vm', memory: 2,}").unwrap();
+        let found = find(&folder);
+        let loaded = load(&folder);
+        fs::write(folder.join("user.json5"), "{hostname: }").unwrap();
+        let broken = load(&folder);
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert!(matches!(missing, Err(MachinegenError::Config(_))));
+        assert_eq!(
+            found,
+            Some((folder.join("user.json5"), UserConfigFormat::Json5))
+        );
+        assert_eq!(
+            loaded.unwrap(),
+            serde_json::json!({"hostname": "vm", "memory": 2})
+        );
+        match broken {
+            Err(MachinegenError::Config(message)) => {
+                assert!(message.contains("user.json5:1:"), "{}", message)
+            }
+            other => panic!("expected a config error, got {:?}", other),
+        }
+    }
+}
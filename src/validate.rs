@@ -0,0 +1,259 @@
+//! Validation of a user config against the keys declared by the machine config tables.
+
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::types::{ConfigEntry, MachineData, ValidationError};
+use crate::values::{self, describe};
+
+/// Checks `config` against the config keys of the machine data.
+pub fn validate(data: &MachineData, config: &Value) -> Vec<ValidationError> {
+    let mut errors: Vec<ValidationError> = Vec::new();
+
+    match config.as_object() {
+        Some(config) => {
+            // Editors may use it to find the schema, it's not part of the config
+            let mut config = config.clone();
+            config.remove("$schema");
+            validate_object(&data.config_keys, &config, "", &mut errors);
+        }
+        None => errors.push(ValidationError {
+            path: String::from("(root)"),
+            message: String::from("the user config must be an object"),
+        }),
+    }
+
+    errors.sort_by(|left, right| left.path.cmp(&right.path));
+    errors
+}
+
+fn validate_object(
+    entries: &HashMap<String, ConfigEntry>,
+    config: &serde_json::Map<String, Value>,
+    parent: &str,
+    errors: &mut Vec<ValidationError>,
+) {
+    let is_files_group = parent.rsplit('.').next() == Some("files");
+
+    for (key, entry) in entries {
+        let path = join_path(parent, key);
+
+        match config.get(key) {
+            // Defaults are checked against the constraints when relating the tables
+            None | Some(Value::Null) => {
+                if entry.mandatory && entry.constraints.default.is_none() {
+                    errors.push(ValidationError {
+                        path,
+                        message: String::from(if is_files_group {
+                            "missing entry for an expected file"
+                        } else {
+                            "missing mandatory key"
+                        }),
+                    });
+                }
+            }
+            Some(value) => validate_entry(entry, value, path, errors),
+        }
+    }
+
+    for key in config.keys() {
+        if !entries.contains_key(key) {
+            errors.push(ValidationError {
+                path: join_path(parent, key),
+                message: String::from(
+                    "unknown key, it's not present in the replace or files tables",
+                ),
+            });
+        }
+    }
+}
+
+fn validate_entry(
+    entry: &ConfigEntry,
+    value: &Value,
+    path: String,
+    errors: &mut Vec<ValidationError>,
+) {
+    if entry.unique {
+        validate_single(entry, value, &path, errors);
+        return;
+    }
+
+    match value.as_array() {
+        Some(values) => {
+            for (index, value) in values.iter().enumerate() {
+                validate_single(entry, value, &format!("{}[{}]", path, index), errors);
+            }
+        }
+        None => errors.push(ValidationError {
+            path,
+            message: format!("expected a list, found {}", describe(value)),
+        }),
+    }
+}
+
+fn validate_single(
+    entry: &ConfigEntry,
+    value: &Value,
+    path: &str,
+    errors: &mut Vec<ValidationError>,
+) {
+    match (&entry.children, value) {
+        (Some(children), Value::Object(object)) => validate_object(children, object, path, errors),
+        (Some(_), value) => errors.push(ValidationError {
+            path: path.to_string(),
+            message: format!("expected a group of keys, found {}", describe(value)),
+        }),
+        (None, Value::Array(_)) | (None, Value::Object(_)) => errors.push(ValidationError {
+            path: path.to_string(),
+            message: format!("expected a single value, found {}", describe(value)),
+        }),
+        (None, value) => {
+            if let Some(primitive) = &entry.value {
+                if let Err(message) = values::check(primitive, &entry.constraints, value) {
+                    errors.push(ValidationError {
+                        path: path.to_string(),
+                        message,
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn join_path(parent: &str, key: &str) -> String {
+    if parent.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", parent, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use serde_json::json;
+
+    const KEYS: &str = concat!(
+        "hostname,user-data,true,true,root,Name,string,,,,,\n",
+        "memory,user-data,true,true,root,Memory,size,2G,,,,\n",
+        "ssh_keys,user-data,false,false,root,Keys,string,,,,,\n",
+        "gateway,user-data,true,true,network,Gateway,ip,,,,,\n",
+    );
+
+    fn problems(data: &MachineData, config: Value) -> Vec<String> {
+        validate(data, &config)
+            .iter()
+            .map(ValidationError::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn valid() {
+        let data = testing::machine_data(KEYS);
+        let config = json!({
+            "$schema": "./schema.json",
+            "hostname": "vm",
+            "ssh_keys": ["ssh-ed25519 AAAA"],
+            "network": {"gateway": "10.0.0.1"},
+        });
+        assert!(validate(&data, &config).is_empty());
+    }
+
+    #[test]
+    fn every_problem_at_once() {
+        let data = testing::machine_data(KEYS);
+        let config = json!({
+            "memory": null,
+            "ssh_keys": "ssh-ed25519 AAAA",
+            "network": {"gateway": ["10.0.0.1"], "dns": "10.0.0.2"},
+            "extra": true,
+        });
+        assert_eq!(
+            problems(&data, config),
+            [
+                "extra: unknown key, it's not present in the replace or files tables",
+                "hostname: missing mandatory key",
+                "network.dns: unknown key, it's not present in the replace or files tables",
+                "network.gateway: expected a single value, found a list",
+                "ssh_keys: expected a list, found a string",
+            ]
+        );
+    }
+
+    #[test]
+    fn values_of_lists() {
+        let data = testing::machine_data(KEYS);
+        let config = json!({
+            "hostname": "vm",
+            "ssh_keys": ["ssh-ed25519 AAAA", 3],
+            "network": {"gateway": "10.0.0"},
+        });
+        let problems = problems(&data, config);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(
+            problems[0].starts_with("network.gateway: "),
+            "{:?}",
+            problems
+        );
+        assert!(problems[1].starts_with("ssh_keys[1]: "), "{:?}", problems);
+    }
+
+    #[test]
+    fn files_groups() {
+        let config = testing::MachineConfig::new(
+            testing::REPLACE,
+            &format!(
+                "{}netplan,Guest,network,/etc/netplan/50.yaml,Netplan config\n",
+                testing::FILES
+            ),
+            testing::TEMPLATES,
+        );
+        let data = config.data().unwrap();
+        assert_eq!(
+            problems(&data, json!({"network": {"files": {}}})),
+            ["network.files.netplan: missing entry for an expected file"]
+        );
+        assert_eq!(
+            problems(&data, json!({"network": []})),
+            ["network: expected a group of keys, found a list"]
+        );
+        assert!(problems(&data, json!({"network": {"files": {"netplan": "50.yaml"}}})).is_empty());
+    }
+
+    #[test]
+    fn not_an_object() {
+        let data = testing::machine_data(KEYS);
+        assert_eq!(
+            problems(&data, json!(["vm"])),
+            ["(root): the user config must be an object"]
+        );
+    }
+
+    #[test]
+    fn optional_groups() {
+        let data = testing::machine_data(concat!(
+            "hostname,user-data,true,true,root,Name,string,,,,,\n",
+            "gateway,user-data,false,true,network,Gateway,ip,,,,,\n",
+            "mtu,user-data,true,true,network,MTU,uint,1500,,,,\n",
+            "mac,user-data,true,true,nics.primary,MAC,mac,,,,,\n",
+            "mtu,user-data,false,true,nics.backup,MTU,uint,,,,,\n",
+        ));
+        // Groups of optional or defaulted keys can be left out, the ones holding a key
+        // that has to be given can't
+        assert_eq!(
+            problems(&data, json!({"hostname": "vm"})),
+            ["nics: missing mandatory key"]
+        );
+        assert_eq!(
+            problems(&data, json!({"hostname": "vm", "nics": {}})),
+            ["nics.primary: missing mandatory key"]
+        );
+        assert!(problems(
+            &data,
+            json!({"hostname": "vm", "nics": {"primary": {"mac": "52:54:00:ab:cd:ef"}}})
+        )
+        .is_empty());
+    }
+}
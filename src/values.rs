@@ -0,0 +1,455 @@
+//! Typed user config values: checking them against the type of their key, and writing them
+//! out the same way whatever way the user wrote them.
+
+use regex::Regex;
+use serde_json::Value;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use crate::types::{ConfigPrimitives, Constraints};
+
+/// Multipliers of the size suffixes, powers of 1024 like QEMU and most disk tools use.
+const SIZE_UNITS: [(char, u64); 5] = [
+    ('K', 1 << 10),
+    ('M', 1 << 20),
+    ('G', 1 << 30),
+    ('T', 1 << 40),
+    ('P', 1 << 50),
+];
+
+/// Checks a single user config value against `primitive`, returning it as written into
+/// templates: IP addresses and networks in their canonical form, MAC addresses in lowercase
+/// with colons, sizes in bytes and paths without redundant separators.
+/// Untyped values (`NoValue` and `Array`) take any scalar as it comes.
+pub fn normalize(primitive: &ConfigPrimitives, value: &Value) -> Result<String, String> {
+    let mismatch = || {
+        format!(
+            "expected {}, found {}",
+            primitive.describe(),
+            describe(value)
+        )
+    };
+    let unparsable = |text: &str| format!("expected {}, got `{}`", primitive.describe(), text);
+
+    match primitive {
+        ConfigPrimitives::NoValue | ConfigPrimitives::Array => match value {
+            Value::String(text) => Ok(text.clone()),
+            Value::Number(number) => Ok(number.to_string()),
+            Value::Bool(boolean) => Ok(boolean.to_string()),
+            _ => Err(mismatch()),
+        },
+        ConfigPrimitives::String => value.as_str().map(String::from).ok_or_else(mismatch),
+        ConfigPrimitives::I32 | ConfigPrimitives::I64 => value
+            .as_i64()
+            .map(|number| number.to_string())
+            .ok_or_else(mismatch),
+        ConfigPrimitives::U32 | ConfigPrimitives::U64 => value
+            .as_u64()
+            .map(|number| number.to_string())
+            .ok_or_else(mismatch),
+        ConfigPrimitives::F32 | ConfigPrimitives::F64 => value
+            .as_f64()
+            .map(|number| number.to_string())
+            .ok_or_else(mismatch),
+        ConfigPrimitives::Bool => value
+            .as_bool()
+            .map(|boolean| boolean.to_string())
+            .ok_or_else(mismatch),
+        ConfigPrimitives::Path => {
+            let text = value.as_str().ok_or_else(mismatch)?;
+            if text.is_empty() || text.contains('\0') {
+                return Err(unparsable(text));
+            }
+            let path: PathBuf = PathBuf::from(text).components().collect();
+            Ok(path.display().to_string())
+        }
+        ConfigPrimitives::Ip => {
+            let text = value.as_str().ok_or_else(mismatch)?;
+            text.trim()
+                .parse::<IpAddr>()
+                .map(|address| address.to_string())
+                .map_err(|_| unparsable(text))
+        }
+        ConfigPrimitives::Cidr => {
+            let text = value.as_str().ok_or_else(mismatch)?;
+            cidr(text.trim()).ok_or_else(|| unparsable(text))
+        }
+        ConfigPrimitives::Mac => {
+            let text = value.as_str().ok_or_else(mismatch)?;
+            mac(text.trim()).ok_or_else(|| unparsable(text))
+        }
+        ConfigPrimitives::Size => match value {
+            Value::Number(number) => number
+                .as_u64()
+                .map(|bytes| bytes.to_string())
+                .ok_or_else(|| unparsable(&number.to_string())),
+            Value::String(text) => size(text.trim())
+                .map(|bytes| bytes.to_string())
+                .ok_or_else(|| unparsable(text)),
+            _ => Err(mismatch()),
+        },
+        ConfigPrimitives::Enum(choices) => {
+            let text = value.as_str().ok_or_else(mismatch)?;
+            if choices.iter().any(|choice| choice == text) {
+                Ok(text.to_string())
+            } else {
+                Err(unparsable(text))
+            }
+        }
+    }
+}
+
+/// Checks a single user config value against `primitive` and the constraints of its key,
+/// returning it normalized. Choices and patterns apply to the normalized value, so `2G` is one
+/// of the choices `2147483648|4G`.
+pub fn check(
+    primitive: &ConfigPrimitives,
+    constraints: &Constraints,
+    value: &Value,
+) -> Result<String, String> {
+    let text = normalize(primitive, value)?;
+
+    if let Some(choices) = &constraints.choices {
+        let allowed = choices
+            .iter()
+            .any(|choice| normalize(primitive, &cell(primitive, choice)).as_ref() == Ok(&text));
+        if !allowed {
+            return Err(format!(
+                "expected one of {}, got `{}`",
+                choices.join("|"),
+                text
+            ));
+        }
+    }
+
+    if let Some(pattern) = &constraints.pattern {
+        let pattern = Regex::new(pattern)
+            .map_err(|error| format!("invalid pattern `{}`: {}", pattern, error))?;
+        if !pattern.is_match(&text) {
+            return Err(format!(
+                "`{}` doesn't match the pattern `{}`",
+                text,
+                pattern.as_str()
+            ));
+        }
+    }
+
+    let (measure, unit) = match primitive {
+        ConfigPrimitives::I32
+        | ConfigPrimitives::I64
+        | ConfigPrimitives::U32
+        | ConfigPrimitives::U64
+        | ConfigPrimitives::F32
+        | ConfigPrimitives::F64
+        | ConfigPrimitives::Size => (text.parse::<f64>().unwrap_or_default(), ""),
+        ConfigPrimitives::NoValue | ConfigPrimitives::Array if value.is_number() => {
+            (text.parse::<f64>().unwrap_or_default(), "")
+        }
+        _ => (text.chars().count() as f64, " characters"),
+    };
+    if let Some(min) = constraints.min {
+        if measure < min {
+            return Err(format!("expected at least {}{}, got `{}`", min, unit, text));
+        }
+    }
+    if let Some(max) = constraints.max {
+        if measure > max {
+            return Err(format!("expected at most {}{}, got `{}`", max, unit, text));
+        }
+    }
+
+    Ok(text)
+}
+
+/// A value written in a table cell, as it would be written in the user config:
+/// numbers and booleans for the types holding them, strings otherwise.
+pub fn cell(primitive: &ConfigPrimitives, text: &str) -> Value {
+    let text = text.trim();
+    let parsed = match primitive {
+        ConfigPrimitives::I32
+        | ConfigPrimitives::I64
+        | ConfigPrimitives::U32
+        | ConfigPrimitives::U64
+        | ConfigPrimitives::F32
+        | ConfigPrimitives::F64
+        | ConfigPrimitives::Bool => serde_json::from_str(text).ok(),
+        ConfigPrimitives::Size => text.parse::<u64>().ok().map(Value::from),
+        _ => None,
+    };
+    parsed.unwrap_or_else(|| Value::String(text.to_string()))
+}
+
+/// Value of the `default` column of a key, a list of its `|` separated items if the key
+/// isn't unique.
+pub fn default_value(
+    primitive: &ConfigPrimitives,
+    constraints: &Constraints,
+    unique: bool,
+) -> Option<Value> {
+    let text = constraints.default.as_ref()?;
+    Some(if unique {
+        cell(primitive, text)
+    } else {
+        Value::Array(text.split('|').map(|item| cell(primitive, item)).collect())
+    })
+}
+
+/// `address/prefix`, the prefix fitting the address family.
+fn cidr(text: &str) -> Option<String> {
+    let (address, prefix) = text.split_once('/')?;
+    let address: IpAddr = address.parse().ok()?;
+    let prefix: u8 = prefix.parse().ok()?;
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+    if prefix > bits {
+        return None;
+    }
+    Some(format!("{}/{}", address, prefix))
+}
+
+/// Six hexadecimal octets, all separated by colons or all by dashes.
+fn mac(text: &str) -> Option<String> {
+    let separator = if text.contains(':') { ':' } else { '-' };
+    let octets: Vec<&str> = text.split(separator).collect();
+    if octets.len() != 6
+        || octets
+            .iter()
+            .any(|octet| octet.len() != 2 || !octet.chars().all(|digit| digit.is_ascii_hexdigit()))
+    {
+        return None;
+    }
+    Some(octets.join(":").to_lowercase())
+}
+
+/// Bytes in a size like `20G`, `512MiB`, `1.5T` or `4096`.
+fn size(text: &str) -> Option<u64> {
+    let number_end = text
+        .find(|character: char| !(character.is_ascii_digit() || character == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(number_end);
+    let number: f64 = number.parse().ok()?;
+
+    let unit = unit.trim();
+    let multiplier = match unit.chars().next() {
+        None => 1,
+        Some('B') | Some('b') if unit.len() == 1 => 1,
+        Some(prefix) => {
+            let suffix = &unit[prefix.len_utf8()..];
+            if !matches!(suffix, "" | "B" | "iB") {
+                return None;
+            }
+            SIZE_UNITS
+                .iter()
+                .find(|(unit, _)| *unit == prefix.to_ascii_uppercase())
+                .map(|(_, multiplier)| *multiplier)?
+        }
+    };
+
+    let bytes = number * multiplier as f64;
+    if bytes.fract() != 0.0 || bytes > u64::MAX as f64 {
+        return None;
+    }
+    Some(bytes as u64)
+}
+
+pub fn describe(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "a list",
+        Value::Object(_) => "a group of keys",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(size("4096"), Some(4096));
+        assert_eq!(size("512B"), Some(512));
+        assert_eq!(size("1K"), Some(1024));
+        assert_eq!(size("512MiB"), Some(512 << 20));
+        assert_eq!(size("20G"), Some(20 << 30));
+        assert_eq!(size("20 GB"), Some(20 << 30));
+        assert_eq!(size("1.5T"), Some(3 << 39));
+        assert_eq!(size("0.5K"), Some(512));
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        assert_eq!(size(""), None);
+        assert_eq!(size("G"), None);
+        assert_eq!(size("20X"), None);
+        assert_eq!(size("20Gbit"), None);
+        assert_eq!(size("1.5B"), None);
+        assert_eq!(size("-1G"), None);
+        assert_eq!(size("1.2.3M"), None);
+        assert_eq!(size("100000P"), None);
+    }
+
+    #[test]
+    fn macs() {
+        assert_eq!(
+            mac("52:54:00:AB:cd:EF"),
+            Some("52:54:00:ab:cd:ef".to_string())
+        );
+        assert_eq!(
+            mac("52-54-00-ab-cd-ef"),
+            Some("52:54:00:ab:cd:ef".to_string())
+        );
+        assert_eq!(mac("52:54:00:ab:cd"), None);
+        assert_eq!(mac("52:54:00:ab:cd:ef:01"), None);
+        assert_eq!(mac("525:4:00:ab:cd:ef"), None);
+        assert_eq!(mac("52:54:00:ab:cd:eg"), None);
+        assert_eq!(mac("+2:54:00:ab:cd:ef"), None);
+        assert_eq!(mac("52:54-00:ab-cd:ef"), None);
+        assert_eq!(mac("52-54-00-ab-cd:ef"), None);
+    }
+
+    #[test]
+    fn cidrs() {
+        assert_eq!(cidr("10.0.0.0/8"), Some("10.0.0.0/8".to_string()));
+        assert_eq!(cidr("10.0.0.1/32"), Some("10.0.0.1/32".to_string()));
+        assert_eq!(cidr("fd00:0:0::1/64"), Some("fd00::1/64".to_string()));
+        assert_eq!(cidr("10.0.0.0/33"), None);
+        assert_eq!(cidr("fd00::/129"), None);
+        assert_eq!(cidr("10.0.0.0"), None);
+        assert_eq!(cidr("10.0.0/8"), None);
+        assert_eq!(cidr("10.0.0.0/-1"), None);
+    }
+
+    #[test]
+    fn normalized_values() {
+        assert_eq!(
+            normalize(&ConfigPrimitives::Size, &Value::from("2G")),
+            Ok("2147483648".to_string())
+        );
+        assert_eq!(
+            normalize(&ConfigPrimitives::Size, &Value::from(1024)),
+            Ok("1024".to_string())
+        );
+        assert_eq!(
+            normalize(&ConfigPrimitives::Mac, &Value::from(" 52-54-00-AB-CD-EF ")),
+            Ok("52:54:00:ab:cd:ef".to_string())
+        );
+        assert_eq!(
+            normalize(&ConfigPrimitives::Cidr, &Value::from(1)),
+            Err(
+                "expected a network in CIDR notation, like 10.0.0.0/24, found a number".to_string()
+            )
+        );
+    }
+
+    fn constraints(
+        choices: Option<&str>,
+        pattern: Option<&str>,
+        min: Option<f64>,
+        max: Option<f64>,
+    ) -> Constraints {
+        Constraints {
+            default: None,
+            min,
+            max,
+            pattern: pattern.map(String::from),
+            choices: choices.map(|choices| choices.split('|').map(String::from).collect()),
+        }
+    }
+
+    #[test]
+    fn choices() {
+        let sizes = constraints(Some("2147483648|4G"), None, None, None);
+        assert_eq!(
+            check(&ConfigPrimitives::Size, &sizes, &Value::from("2G")),
+            Ok("2147483648".to_string())
+        );
+        assert_eq!(
+            check(&ConfigPrimitives::Size, &sizes, &Value::from(4u64 << 30)),
+            Ok("4294967296".to_string())
+        );
+        assert_eq!(
+            check(&ConfigPrimitives::Size, &sizes, &Value::from("1G")),
+            Err("expected one of 2147483648|4G, got `1073741824`".to_string())
+        );
+        let cores = constraints(Some("1|2|4"), None, None, None);
+        assert!(check(&ConfigPrimitives::U32, &cores, &Value::from(2)).is_ok());
+        assert!(check(&ConfigPrimitives::U32, &cores, &Value::from(3)).is_err());
+    }
+
+    #[test]
+    fn patterns() {
+        let names = constraints(None, Some("^[a-z][a-z0-9-]*$"), None, None);
+        assert!(check(&ConfigPrimitives::String, &names, &Value::from("web-1")).is_ok());
+        assert_eq!(
+            check(&ConfigPrimitives::String, &names, &Value::from("Web")),
+            Err("`Web` doesn't match the pattern `^[a-z][a-z0-9-]*$`".to_string())
+        );
+        // Patterns apply to the normalized value
+        let macs = constraints(None, Some("^52:54:00:"), None, None);
+        assert!(check(
+            &ConfigPrimitives::Mac,
+            &macs,
+            &Value::from("52-54-00-AB-CD-EF")
+        )
+        .is_ok());
+
+        let invalid = constraints(None, Some("("), None, None);
+        assert!(
+            check(&ConfigPrimitives::String, &invalid, &Value::from("a"))
+                .unwrap_err()
+                .starts_with("invalid pattern `(`")
+        );
+    }
+
+    #[test]
+    fn bounds() {
+        let cpus = constraints(None, None, Some(1.0), Some(64.0));
+        assert!(check(&ConfigPrimitives::U32, &cpus, &Value::from(64)).is_ok());
+        assert_eq!(
+            check(&ConfigPrimitives::U32, &cpus, &Value::from(0)),
+            Err("expected at least 1, got `0`".to_string())
+        );
+        let memory = constraints(None, None, Some(1073741824.0), None);
+        assert!(check(&ConfigPrimitives::Size, &memory, &Value::from("512M")).is_err());
+        assert!(check(&ConfigPrimitives::Size, &memory, &Value::from("1G")).is_ok());
+
+        // Text is bounded by its length
+        let names = constraints(None, None, Some(2.0), Some(4.0));
+        assert!(check(&ConfigPrimitives::String, &names, &Value::from("añb")).is_ok());
+        assert_eq!(
+            check(&ConfigPrimitives::String, &names, &Value::from("hosts")),
+            Err("expected at most 4 characters, got `hosts`".to_string())
+        );
+        // Untyped numbers are bounded by their value
+        assert!(check(&ConfigPrimitives::NoValue, &names, &Value::from(3)).is_ok());
+        assert!(check(&ConfigPrimitives::NoValue, &names, &Value::from("3")).is_err());
+    }
+
+    #[test]
+    fn defaults() {
+        let mut constraints = Constraints {
+            default: Some(String::from("22 | 80")),
+            ..Constraints::default()
+        };
+        assert_eq!(
+            default_value(&ConfigPrimitives::U32, &constraints, false),
+            Some(serde_json::json!([22, 80]))
+        );
+        constraints.default = Some(String::from("2G"));
+        assert_eq!(
+            default_value(&ConfigPrimitives::Size, &constraints, true),
+            Some(Value::from("2G"))
+        );
+        constraints.default = Some(String::from("1024"));
+        assert_eq!(
+            default_value(&ConfigPrimitives::Size, &constraints, true),
+            Some(Value::from(1024))
+        );
+        assert_eq!(
+            default_value(&ConfigPrimitives::Size, &Constraints::default(), true),
+            None
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! The workspace: a folder holding a `.machinegen` folder, and the standard paths inside it.
+//!
+//! The machine config and the dependencies are shared by every machine of a workspace.
+//! The default machine keeps its user config next to the machine config and its build and
+//! state right in `.machinegen`, named machines keep theirs in `.machinegen/machines/<name>`.
+
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use crate::types::MachinegenError;
+
+/// Name of the folder machinegen keeps everything it pulls, builds and deploys in.
+pub const FOLDER: &str = ".machinegen";
+
+/// Environment variable pointing at the workspace, used when none is given explicitly.
+pub const HOME_VARIABLE: &str = "MACHINEGEN_HOME";
+
+/// Name of the machine used when none is given.
+pub const DEFAULT_MACHINE: &str = "default";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Workspace {
+    dir: PathBuf,
+    machine: Option<String>,
+}
+
+impl Workspace {
+    /// Workspace rooted at `dir`, the folder holding (or that will hold) `.machinegen`.
+    /// Passing the `.machinegen` folder itself is accepted as well.
+    pub fn new(dir: &Path) -> Workspace {
+        let dir = match dir.file_name() {
+            Some(name) if name == FOLDER => dir.parent().unwrap_or(dir),
+            _ => dir,
+        };
+        Workspace {
+            dir: dir.to_path_buf(),
+            machine: None,
+        }
+    }
+
+    /// The same workspace, working on the machine called `name`.
+    /// Names are made of ASCII letters, digits, `-` and `_`.
+    pub fn with_machine(&self, name: &str) -> Result<Workspace, MachinegenError> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|character| character.is_ascii_alphanumeric() || "-_".contains(character))
+        {
+            return Err(MachinegenError::Config(format!(
+                "`{}` is not a valid machine name, use only ASCII letters, digits, `-` and `_`.",
+                name
+            )));
+        }
+
+        Ok(Workspace {
+            dir: self.dir.clone(),
+            machine: if name == DEFAULT_MACHINE {
+                None
+            } else {
+                Some(name.to_string())
+            },
+        })
+    }
+
+    /// Resolves the workspace to use: `explicit` when given, otherwise the one pointed at by
+    /// `MACHINEGEN_HOME`, otherwise the closest folder holding `.machinegen` going up from the
+    /// current folder, like git does for `.git`. Without any, the current folder is used, so a
+    /// first `pull` creates the workspace there.
+    pub fn resolve(explicit: Option<&Path>) -> Result<Workspace, MachinegenError> {
+        let current =
+            env::current_dir().map_err(|error| MachinegenError::io(Path::new("."), error))?;
+
+        let given = match explicit {
+            Some(dir) => Some((dir.to_path_buf(), "--workspace")),
+            None => env::var_os(HOME_VARIABLE)
+                .filter(|value| !value.is_empty())
+                .map(|value| (PathBuf::from(value), HOME_VARIABLE)),
+        };
+        if let Some((dir, source)) = given {
+            let dir = current.join(dir);
+            if !dir.is_dir() {
+                return Err(MachinegenError::Precondition(format!(
+                    "The workspace {} given by {} is not a folder.",
+                    dir.display(),
+                    source
+                )));
+            }
+            return Ok(Workspace::new(&dir));
+        }
+
+        Ok(Workspace::discover(&current).unwrap_or_else(|| Workspace::new(&current)))
+    }
+
+    /// Closest workspace holding a `.machinegen` folder, starting at `start` and going up.
+    pub fn discover(start: &Path) -> Option<Workspace> {
+        start
+            .ancestors()
+            .find(|dir| dir.join(FOLDER).is_dir())
+            .map(Workspace::new)
+    }
+
+    /// Folder holding `.machinegen`.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The `.machinegen` folder.
+    pub fn root(&self) -> PathBuf {
+        self.dir.join(FOLDER)
+    }
+
+    /// Path inside the `.machinegen` folder.
+    pub fn path(&self, subpath: &[&str]) -> PathBuf {
+        let mut path = self.root();
+        for component in subpath {
+            path.push(component);
+        }
+        path
+    }
+
+    /// Name of the machine worked on.
+    pub fn machine(&self) -> &str {
+        self.machine.as_deref().unwrap_or(DEFAULT_MACHINE)
+    }
+
+    /// Names of the machines of the workspace, the default one first. The default machine
+    /// is always there, named ones are the folders in `.machinegen/machines`.
+    pub fn machines(&self) -> Result<Vec<String>, MachinegenError> {
+        let folder = self.path(&["machines"]);
+        let mut names: Vec<String> = Vec::new();
+        if folder.is_dir() {
+            for entry in
+                fs::read_dir(&folder).map_err(|error| MachinegenError::io(&folder, error))?
+            {
+                let entry = entry.map_err(|error| MachinegenError::io(&folder, error))?;
+                if entry.path().is_dir() {
+                    names.push(entry.file_name().to_string_lossy().to_string());
+                }
+            }
+        }
+        names.sort();
+        names.insert(0, DEFAULT_MACHINE.to_string());
+        Ok(names)
+    }
+
+    /// Folder of the machine worked on, holding its user config, build and state.
+    /// For the default machine, that's the `.machinegen` folder itself.
+    pub fn machine_root(&self) -> PathBuf {
+        match &self.machine {
+            Some(name) => self.path(&["machines", name]),
+            None => self.root(),
+        }
+    }
+
+    /// Folder holding the user config of the machine worked on.
+    pub fn user(&self) -> PathBuf {
+        match &self.machine {
+            Some(_) => self.machine_root(),
+            None => self.config(),
+        }
+    }
+
+    /// Machine config, as pulled from its repository.
+    pub fn config(&self) -> PathBuf {
+        self.path(&["config"])
+    }
+
+    /// Commit the machine config was pulled at.
+    pub fn config_lock(&self) -> PathBuf {
+        self.path(&["config.lock"])
+    }
+
+    /// Machine config tables.
+    pub fn tables(&self) -> PathBuf {
+        self.path(&["config", "tables"])
+    }
+
+    /// Runtime dependencies and machine images.
+    pub fn deps(&self) -> PathBuf {
+        self.path(&["deps"])
+    }
+
+    /// Executables of the runtime dependencies.
+    pub fn bin(&self) -> PathBuf {
+        self.path(&["deps", "bin"])
+    }
+
+    /// Machine images.
+    pub fn images(&self) -> PathBuf {
+        self.path(&["deps", "images"])
+    }
+
+    /// Rendered templates, the seed image and the Terraform project of the machine worked on.
+    pub fn build(&self) -> PathBuf {
+        self.machine_root().join("build")
+    }
+
+    /// Record of the deployment of the machine worked on.
+    pub fn state(&self) -> PathBuf {
+        self.machine_root().join("state")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        let workspace = Workspace::new(Path::new("/work"));
+        assert_eq!(workspace, Workspace::new(Path::new("/work/.machinegen")));
+        assert_eq!(workspace.dir(), Path::new("/work"));
+        assert_eq!(workspace.machine(), DEFAULT_MACHINE);
+        assert_eq!(workspace.root(), Path::new("/work/.machinegen"));
+        assert_eq!(
+            workspace.tables(),
+            Path::new("/work/.machinegen/config/tables")
+        );
+        assert_eq!(
+            workspace.images(),
+            Path::new("/work/.machinegen/deps/images")
+        );
+        assert_eq!(workspace.user(), Path::new("/work/.machinegen/config"));
+        assert_eq!(workspace.build(), Path::new("/work/.machinegen/build"));
+        assert_eq!(workspace.state(), Path::new("/work/.machinegen/state"));
+    }
+
+    #[test]
+    fn discovered_upwards() {
+        let dir = env::temp_dir().join(format!("machinegen-workspace-{}", std::process::id()));
+        let nested = dir.join("project/src/module");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(dir.join("project").join(FOLDER)).unwrap();
+
+        assert_eq!(
+            Workspace::discover(&nested),
+            Some(Workspace::new(&dir.join("project")))
+        );
+        assert_eq!(
+            Workspace::discover(&dir.join("project")),
+            Some(Workspace::new(&dir.join("project")))
+        );
+        // A `.machinegen` file is not a workspace
+        fs::write(dir.join(FOLDER), "").unwrap();
+        assert_eq!(Workspace::discover(&dir), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn named_machines() {
+        let workspace = Workspace::new(Path::new("/work"));
+        let web = workspace.with_machine("web-1").unwrap();
+        assert_eq!(web.machine(), "web-1");
+        assert_eq!(
+            web.machine_root(),
+            Path::new("/work/.machinegen/machines/web-1")
+        );
+        assert_eq!(web.user(), web.machine_root());
+        assert_eq!(
+            web.build(),
+            Path::new("/work/.machinegen/machines/web-1/build")
+        );
+        assert_eq!(
+            web.state(),
+            Path::new("/work/.machinegen/machines/web-1/state")
+        );
+        // The machine config and the dependencies are shared
+        assert_eq!(web.tables(), workspace.tables());
+        assert_eq!(web.deps(), workspace.deps());
+
+        assert_eq!(web.with_machine(DEFAULT_MACHINE).unwrap(), workspace);
+        for name in ["", "web.1", "../web", "wéb"] {
+            assert!(workspace.with_machine(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn machines_listed() {
+        let dir = env::temp_dir().join(format!("machinegen-machines-{}", std::process::id()));
+        let workspace = Workspace::new(&dir);
+        assert_eq!(workspace.machines().unwrap(), [DEFAULT_MACHINE]);
+
+        fs::create_dir_all(workspace.path(&["machines", "web"])).unwrap();
+        fs::create_dir_all(workspace.path(&["machines", "db"])).unwrap();
+        fs::write(workspace.path(&["machines", "notes.txt"]), "").unwrap();
+        assert_eq!(
+            workspace.machines().unwrap(),
+            [DEFAULT_MACHINE, "db", "web"]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
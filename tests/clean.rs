@@ -0,0 +1,98 @@
+//! `clean`: removing each category of files under `.machinegen`, and keeping a Terraform
+//! state that still tracks deployed resources.
+
+mod common;
+
+use common::Fixture;
+
+const LIVE_STATE: &str =
+    r#"{"resources":[{"mode":"managed","type":"libvirt_domain","name":"vm"}]}"#;
+
+/// A workspace with something in every category.
+fn populated(name: &str) -> Fixture {
+    let fixture = Fixture::new(name);
+    fixture.machine_config();
+    fixture.write(".machinegen/config.lock", "commit");
+    fixture.write(".machinegen/deps/bin/tool", "tool");
+    fixture.write(".machinegen/deps/images/base.qcow2", "image");
+    fixture.write(".machinegen/build/seed.iso", "seed");
+    fixture.write(".machinegen/state/deployment.json", "{}");
+    fixture
+}
+
+#[test]
+fn flags_narrow_the_categories() {
+    let fixture = populated("clean-flags");
+
+    fixture.succeed(&["clean", "--deps", "--runtime"]);
+    assert!(!fixture.workspace("deps/bin").exists());
+    assert!(fixture.workspace("deps/images/base.qcow2").exists());
+
+    fixture.succeed(&["clean", "--config", "--user"]);
+    assert!(!fixture.workspace("config/user.json").exists());
+    assert!(fixture.workspace("config/tables/replace.csv").exists());
+    assert!(fixture.workspace("config.lock").exists());
+
+    fixture.succeed(&["clean", "--config", "--machine"]);
+    assert!(!fixture.workspace("config/tables").exists());
+    assert!(!fixture.workspace("config.lock").exists());
+
+    fixture.succeed(&["clean", "--generated"]);
+    assert!(!fixture.workspace("build").exists());
+    assert!(!fixture.workspace("state").exists());
+    assert!(fixture.workspace("deps/images/base.qcow2").exists());
+
+    fixture.succeed(&["clean", "--all"]);
+    assert!(!fixture.workspace("").exists());
+
+    let stdout = fixture.succeed(&["clean", "--all"]);
+    assert!(stdout.contains("Nothing to clean."), "{}", stdout);
+}
+
+#[test]
+fn dry_run_removes_nothing() {
+    let fixture = populated("clean-dry-run");
+
+    let stdout = fixture.succeed(&["clean", "--deps", "--dry-run"]);
+    assert!(stdout.contains("Would remove"), "{}", stdout);
+    assert!(stdout.contains("Cleaning would free 9 B."), "{}", stdout);
+    assert!(fixture.workspace("deps/bin/tool").exists());
+    assert!(fixture.workspace("deps/images/base.qcow2").exists());
+}
+
+#[test]
+fn live_state_needs_force() {
+    let fixture = populated("clean-live");
+    fixture.write(".machinegen/build/terraform/terraform.tfstate", LIVE_STATE);
+
+    let stderr = fixture.fail(&["clean", "--generated"], 9);
+    assert!(stderr.contains("libvirt_domain.vm"), "{}", stderr);
+    assert_eq!(
+        fixture.read(".machinegen/build/terraform/terraform.tfstate"),
+        LIVE_STATE
+    );
+    assert!(fixture.workspace("state/deployment.json").exists());
+
+    let output = fixture.machinegen(&["clean", "--generated", "--dry-run"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("requires --force"));
+    assert!(fixture
+        .workspace("build/terraform/terraform.tfstate")
+        .exists());
+
+    fixture.succeed(&["clean", "--generated", "--force"]);
+    assert!(!fixture.workspace("build").exists());
+}
+
+#[test]
+fn deployment_record_needs_force() {
+    let fixture = populated("clean-record");
+    fixture.write(
+        ".machinegen/state/deployment.json",
+        r#"{"status": "deployed"}"#,
+    );
+
+    let stderr = fixture.fail(&["clean", "--generated"], 9);
+    assert!(stderr.contains("still deployed"), "{}", stderr);
+    assert!(fixture.workspace("state/deployment.json").exists());
+}
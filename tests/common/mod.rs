@@ -0,0 +1,286 @@
+//! Fixtures shared by the integration tests: throwaway workspaces, a local HTTP server and a
+//! fake `terraform`, so every flow runs offline.
+
+#![allow(dead_code)]
+
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{env, fs, thread};
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// Folder removed once the test is done with it, holding a workspace in `dir` and whatever
+/// else the test needs next to it.
+pub struct Fixture {
+    pub dir: PathBuf,
+}
+
+impl Fixture {
+    pub fn new(name: &str) -> Fixture {
+        let dir = env::temp_dir().join(format!(
+            "machinegen-test-{}-{}-{}",
+            name,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Fixture { dir }
+    }
+
+    pub fn path(&self, relative: &str) -> PathBuf {
+        self.dir.join(relative)
+    }
+
+    /// Path inside the `.machinegen` folder of the workspace.
+    pub fn workspace(&self, relative: &str) -> PathBuf {
+        self.dir.join(".machinegen").join(relative)
+    }
+
+    /// Writes `content` at `relative`, creating the folders leading to it.
+    pub fn write(&self, relative: &str, content: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.path(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    pub fn read(&self, relative: &str) -> String {
+        fs::read_to_string(self.path(relative)).unwrap()
+    }
+
+    /// Runs machinegen in the workspace, with the fake `terraform` first in `PATH`.
+    pub fn machinegen(&self, args: &[&str]) -> Output {
+        let mut paths = vec![self.path("bin")];
+        if let Some(path) = env::var_os("PATH") {
+            paths.extend(env::split_paths(&path));
+        }
+        Command::new(env!("CARGO_BIN_EXE_machinegen"))
+            .arg("--workspace")
+            .arg(&self.dir)
+            .args(args)
+            .current_dir(&self.dir)
+            .env("PATH", env::join_paths(paths).unwrap())
+            .env("NO_COLOR", "1")
+            .env_remove("MACHINEGEN_HOME")
+            .output()
+            .unwrap()
+    }
+
+    /// Runs machinegen, failing the test unless it succeeds.
+    pub fn succeed(&self, args: &[&str]) -> String {
+        let output = self.machinegen(args);
+        assert!(
+            output.status.success(),
+            "machinegen {} failed:\n{}{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+
+    /// Runs machinegen, failing the test unless it exits with `code`. Returns its stderr.
+    pub fn fail(&self, args: &[&str], code: i32) -> String {
+        let output = self.machinegen(args);
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        assert_eq!(
+            output.status.code(),
+            Some(code),
+            "machinegen {}:\n{}{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stdout),
+            stderr
+        );
+        stderr
+    }
+
+    /// Installs a fake `terraform` in the `bin` folder put first in `PATH`. Plans either
+    /// create a `libvirt_domain.vm` or, with `-destroy`, delete it, and applying them
+    /// writes the state accordingly. Every call is logged to `terraform.log` in the project.
+    pub fn fake_terraform(&self) {
+        let path = self.write(
+            "bin/terraform",
+            r#"#!/bin/sh
+echo "$*" >> terraform.log
+out=""
+action=create
+for arg in "$@"; do
+  case "$arg" in
+    -out=*) out="${arg#-out=}" ;;
+    -destroy) action=delete ;;
+  esac
+done
+case "$1" in
+  init) mkdir -p .terraform && touch .terraform.lock.hcl ;;
+  plan) echo "$action" > "$out" ;;
+  show) echo "{\"resource_changes\":[{\"address\":\"libvirt_domain.vm\",\"change\":{\"actions\":[\"$(cat "$3")\"]}}]}" ;;
+  apply)
+    if [ "$(cat "$3")" = delete ]; then
+      echo '{"resources":[]}' > terraform.tfstate
+    else
+      echo '{"resources":[{"mode":"managed","type":"libvirt_domain","name":"vm"}]}' > terraform.tfstate
+    fi ;;
+  output) echo '{"ip":{"value":"10.0.0.5"}}' ;;
+  *) exit 1 ;;
+esac
+"#,
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+
+    /// A minimal machine config: a hostname for the guest and the memory for the host.
+    pub fn machine_config(&self) {
+        self.write(
+            ".machinegen/config/tables/replace.csv",
+            "string,template,mandatory,unique,config_parent,description,type\n\
+            hostname,user-data,true,true,root,Hostname of the machine,string\n\
+            memory,main.tf,true,true,root,Memory of the machine,size\n",
+        );
+        self.write(
+            ".machinegen/config/tables/templates.csv",
+            "name,system,source,target,description\n\
+            user-data,Guest,templates/user-data,user-data,Cloud-init user data\n\
+            main.tf,Host,templates/main.tf,main.tf,Terraform project\n",
+        );
+        self.write(
+            ".machinegen/config/tables/files.csv",
+            "name,system,config_parent,target,description\n",
+        );
+        self.write(
+            ".machinegen/config/templates/user-data",
+            "#cloud-config\nhostname: {{ hostname }}\n",
+        );
+        self.write(
+            ".machinegen/config/templates/main.tf",
+            "locals {\n  memory = {{ memory }}\n}\n",
+        );
+        self.write(
+            ".machinegen/config/user.json",
+            r#"{"hostname": "vm", "memory": "1G"}"#,
+        );
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn sha256(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+pub fn file_url(path: &Path) -> String {
+    format!("file://{}", path.display())
+}
+
+/// Request line and `Range` header, if any, of a request the server got.
+pub type Request = (String, Option<String>);
+
+/// HTTP server on a local port, serving `files` by path and honoring `Range` requests.
+/// With `truncate_first`, the first response is cut halfway through, like a dropped connection.
+pub struct Server {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Server {
+    pub fn start(files: Vec<(&str, Vec<u8>)>, truncate_first: bool) -> Server {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let files: Vec<(String, Vec<u8>)> = files
+            .into_iter()
+            .map(|(path, content)| (path.to_string(), content))
+            .collect();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let served = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                let mut range: Option<String> = None;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    if request.is_empty() {
+                        request = line.trim().to_string();
+                    } else if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("range") {
+                            range = Some(value.trim().to_string());
+                        }
+                    }
+                    line.clear();
+                }
+                let first = {
+                    let mut served = served.lock().unwrap();
+                    served.push((request.clone(), range.clone()));
+                    served.len() == 1
+                };
+
+                let path = request.split(' ').nth(1).unwrap_or("/");
+                let content = match files.iter().find(|(name, _)| name == path) {
+                    Some((_, content)) => content,
+                    None => {
+                        let _ = stream.write_all(
+                            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        );
+                        continue;
+                    }
+                };
+                let offset = range
+                    .as_deref()
+                    .and_then(|range| range.strip_prefix("bytes="))
+                    .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok())
+                    .unwrap_or(0)
+                    .min(content.len());
+                let head = if offset > 0 {
+                    format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n",
+                        offset,
+                        content.len() - 1,
+                        content.len()
+                    )
+                } else {
+                    String::from("HTTP/1.1 200 OK\r\n")
+                };
+                let body = &content[offset..];
+                let _ = stream.write_all(
+                    format!(
+                        "{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                        head,
+                        body.len()
+                    )
+                    .as_bytes(),
+                );
+                let sent = if first && truncate_first {
+                    &body[..body.len() / 2]
+                } else {
+                    body
+                };
+                let _ = stream.write_all(sent);
+            }
+        });
+
+        Server { url, requests }
+    }
+
+    /// Every request served so far.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
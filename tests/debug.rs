@@ -0,0 +1,66 @@
+//! `debug`: the tables, machine data, config tree and replacements as JSON.
+
+mod common;
+
+use common::Fixture;
+use serde_json::{json, Value};
+
+fn shown(fixture: &Fixture, args: &[&str]) -> Value {
+    let stdout = fixture.succeed(args);
+    serde_json::from_str(&stdout).unwrap_or_else(|error| panic!("{}: {}", error, stdout))
+}
+
+#[test]
+fn tables_and_data() {
+    let fixture = Fixture::new("debug-tables");
+    fixture.machine_config();
+
+    let rows = shown(&fixture, &["debug", "table", "replace"]);
+    assert_eq!(rows[0]["string"], "hostname");
+    assert_eq!(rows[1]["type"], json!("Size"));
+
+    let stdout = fixture.succeed(&["debug", "--format", "compact", "data"]);
+    assert_eq!(stdout.lines().count(), 1);
+    let data: Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(
+        data["templates"]["user-data"]["replacements"]["hostname"]["mandatory"],
+        true
+    );
+
+    // Always there, but not advertised
+    let help = fixture.succeed(&["--help"]);
+    let subcommands = help.split_once("SUBCOMMANDS:").unwrap().1;
+    assert!(subcommands.contains("validate"), "{}", subcommands);
+    assert!(!subcommands.contains("debug"), "{}", subcommands);
+    assert_eq!(shown(&fixture, &["inspect", "data"]), data);
+}
+
+#[test]
+fn config_and_replacements() {
+    let fixture = Fixture::new("debug-config");
+    fixture.machine_config();
+    fixture.write(
+        ".machinegen/config/user.json",
+        r#"{"hostname": "vm", "memory": "lots"}"#,
+    );
+
+    let tree = shown(&fixture, &["debug", "config"]);
+    assert_eq!(tree["hostname"]["value"], "vm");
+    assert_eq!(tree["memory"]["mandatory"], true);
+
+    let replacements = shown(&fixture, &["debug", "replacements"]);
+    assert_eq!(replacements["user-data"]["hostname"]["value"], "vm");
+    assert!(replacements["main.tf"]["memory"]["error"]
+        .as_str()
+        .unwrap()
+        .contains("expected a size"));
+
+    let replacements = shown(&fixture, &["debug", "replacements", "main.tf"]);
+    assert!(replacements.get("user-data").is_none());
+    fixture.fail(&["debug", "replacements", "vendor-data"], 4);
+
+    // Without a readable user config, the tables are shown all the same
+    fixture.write(".machinegen/config/user.json", "{");
+    let tree = shown(&fixture, &["debug", "config"]);
+    assert_eq!(tree["hostname"]["value"], Value::Null);
+}
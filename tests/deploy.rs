@@ -0,0 +1,215 @@
+//! `build`, `deploy` and `destroy` against a fake `terraform` put first in `PATH`.
+
+mod common;
+
+use common::{file_url, Fixture};
+use serde_json::{json, Value};
+use std::fs;
+
+const BUILD: [&str; 4] = ["build", "--all", "--image", "base"];
+
+/// A workspace with a machine config, a user config and a pulled `base` image.
+fn ready(name: &str) -> Fixture {
+    let fixture = Fixture::new(name);
+    fixture.fake_terraform();
+    fixture.machine_config();
+    let image = fixture.write("upstream/base.qcow2", "image");
+    fixture.succeed(&["pull", "deps", "--image-url", &file_url(&image)]);
+    fixture
+}
+
+fn deployment(fixture: &Fixture) -> Value {
+    serde_json::from_str(&fixture.read(".machinegen/state/deployment.json")).unwrap()
+}
+
+#[test]
+fn builds_a_planned_project() {
+    let fixture = ready("build-project");
+
+    fixture.succeed(&BUILD);
+    assert!(fixture.workspace("build/seed.iso").is_file());
+    assert_eq!(
+        fixture.read(".machinegen/build/cloud-init/user-data"),
+        "#cloud-config\nhostname: vm\n"
+    );
+    assert_eq!(
+        fixture.read(".machinegen/build/terraform/main.tf"),
+        "locals {\n  memory = 1073741824\n}\n"
+    );
+    let variables = fixture.read(".machinegen/build/terraform/machinegen.tf");
+    assert!(variables.contains("base.qcow2"), "{}", variables);
+    assert!(variables.contains("seed.iso"), "{}", variables);
+    assert!(fixture
+        .workspace("build/terraform/machinegen.tfplan")
+        .is_file());
+    let calls = fixture.read(".machinegen/build/terraform/terraform.log");
+    assert!(calls.starts_with("init"), "{}", calls);
+    assert!(calls.contains("plan"), "{}", calls);
+}
+
+#[test]
+fn forced_rebuild_keeps_terraform_state() {
+    let fixture = ready("build-rebuild");
+    fixture.succeed(&BUILD);
+    let state = r#"{"resources":[{"mode":"managed","type":"libvirt_domain","name":"vm"}]}"#;
+    fixture.write(".machinegen/build/terraform/terraform.tfstate", state);
+    fixture.write(".machinegen/build/terraform/stale.tf", "");
+
+    fixture.succeed(&["build", "--all", "--image", "base", "--force"]);
+    let project = fixture.workspace("build/terraform");
+    assert_eq!(
+        fixture.read(".machinegen/build/terraform/terraform.tfstate"),
+        state
+    );
+    assert!(project.join(".terraform").is_dir());
+    assert!(project.join(".terraform.lock.hcl").is_file());
+    assert!(!project.join("stale.tf").exists());
+    assert!(project.join("main.tf").is_file());
+}
+
+#[test]
+fn missing_image() {
+    let fixture = Fixture::new("build-image");
+    fixture.fake_terraform();
+    fixture.machine_config();
+
+    fixture.fail(&["build", "--terraform", "--image", "base"], 9);
+    assert!(!fixture
+        .workspace("build/terraform/machinegen.tfplan")
+        .exists());
+}
+
+#[test]
+fn deploys_the_plan_once() {
+    let fixture = ready("deploy-plan");
+    fixture.succeed(&BUILD);
+
+    let stdout = fixture.succeed(&["deploy", "--yes"]);
+    assert!(stdout.contains("+ libvirt_domain.vm"), "{}", stdout);
+    assert!(stdout.contains("ip = \"10.0.0.5\""), "{}", stdout);
+    let record = deployment(&fixture);
+    assert_eq!(record["status"], "deployed");
+    assert_eq!(record["outputs"]["ip"], "10.0.0.5");
+
+    // A saved plan is applied only once
+    let stderr = fixture.fail(&["deploy", "--yes"], 9);
+    assert!(stderr.contains("no Terraform plan to deploy"), "{}", stderr);
+}
+
+#[test]
+fn nothing_to_deploy_without_a_plan() {
+    let fixture = ready("deploy-unbuilt");
+    let stderr = fixture.fail(&["deploy", "--yes"], 9);
+    assert!(stderr.contains("no Terraform plan to deploy"), "{}", stderr);
+    assert!(!fixture.workspace("state/deployment.json").exists());
+}
+
+#[test]
+fn stale_plans_are_not_deployed() {
+    let fixture = ready("deploy-stale");
+    fixture.succeed(&BUILD);
+
+    fixture.write(
+        ".machinegen/build/terraform/extra.tf",
+        "# changed by hand\n",
+    );
+    let stderr = fixture.fail(&["deploy", "--yes"], 9);
+    assert!(
+        stderr.contains("changed since the plan was made"),
+        "{}",
+        stderr
+    );
+    assert!(!fixture
+        .workspace("build/terraform/terraform.tfstate")
+        .exists());
+}
+
+#[test]
+fn destroys_the_deployment() {
+    let fixture = ready("destroy-cycle");
+    fixture.succeed(&BUILD);
+    fixture.succeed(&["deploy", "--yes"]);
+
+    let stdout = fixture.succeed(&["destroy", "--yes"]);
+    assert!(stdout.contains("- libvirt_domain.vm"), "{}", stdout);
+    let record = deployment(&fixture);
+    assert_eq!(record["status"], "destroyed");
+    assert_eq!(record["outputs"], json!({}));
+    assert!(!fixture
+        .workspace("build/terraform/machinegen-destroy.tfplan")
+        .exists());
+    // Generated files can go now
+    fixture.succeed(&["clean", "--generated"]);
+
+    let stdout = fixture.succeed(&["destroy", "--yes"]);
+    assert!(stdout.contains("Nothing is deployed"), "{}", stdout);
+}
+
+#[test]
+fn destroys_after_a_forced_rebuild() {
+    let fixture = ready("destroy-rebuild");
+    fixture.succeed(&BUILD);
+    fixture.succeed(&["deploy", "--yes"]);
+    fixture.succeed(&["build", "--all", "--image", "base", "--force"]);
+
+    // The rebuilt project still knows about the deployed machine
+    let stdout = fixture.succeed(&["destroy", "--yes"]);
+    assert!(stdout.contains("- libvirt_domain.vm"), "{}", stdout);
+    assert_eq!(deployment(&fixture)["status"], "destroyed");
+}
+
+#[test]
+fn reports_drift() {
+    let fixture = ready("destroy-drift");
+    fixture.succeed(&BUILD);
+    fixture.succeed(&["deploy", "--yes"]);
+
+    // The domain was undefined by hand, the refresh before planning finds it gone
+    fs::rename(
+        fixture.path("bin/terraform"),
+        fixture.path("bin/terraform.real"),
+    )
+    .unwrap();
+    let wrapper = fixture.write(
+        "bin/terraform",
+        r#"#!/bin/sh
+if [ "$1" = show ] && [ "$(cat "$3")" = delete ]; then
+  echo '{"resource_drift":[{"address":"libvirt_domain.vm","change":{"actions":["delete"]}}],"resource_changes":[]}'
+else
+  exec "$(dirname "$0")/terraform.real" "$@"
+fi
+"#,
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(wrapper, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let output = fixture.machinegen(&["destroy", "--yes"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("libvirt_domain.vm was removed outside of Terraform"),
+        "{}",
+        stderr
+    );
+    let record = deployment(&fixture);
+    assert_eq!(record["status"], "destroyed");
+    assert_eq!(record["drift"], json!(["libvirt_domain.vm"]));
+}
+
+#[test]
+fn deployment_record_without_state() {
+    let fixture = ready("destroy-record");
+    fixture.write(
+        ".machinegen/state/deployment.json",
+        r#"{"status": "deployed"}"#,
+    );
+
+    let output = fixture.machinegen(&["destroy", "--yes"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("removed by hand"), "{}", stderr);
+    assert_eq!(deployment(&fixture)["status"], "destroyed");
+}
@@ -0,0 +1,92 @@
+//! Logging: verbosity levels, JSON records and the log file capturing subprocess output.
+
+mod common;
+
+use common::{file_url, Fixture};
+use serde_json::Value;
+
+fn records(output: &[u8]) -> Vec<Value> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn json_records() {
+    let fixture = Fixture::new("log-json");
+    fixture.machine_config();
+
+    let output = fixture.machinegen(&["--log-format", "json", "validate"]);
+    assert!(output.status.success());
+    let stdout = records(&output.stdout);
+    assert_eq!(stdout.len(), 1);
+    assert_eq!(stdout[0]["level"], "success");
+    assert_eq!(stdout[0]["message"], "User config is valid.");
+    assert!(stdout[0]["timestamp"].is_number());
+
+    fixture.write(".machinegen/config/user.json", "{}");
+    let output = fixture.machinegen(&["--log-format", "json", "validate"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(output.stdout.is_empty());
+    let stderr = records(&output.stderr);
+    assert_eq!(stderr[0]["level"], "error");
+    assert!(stderr[0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("hostname: missing mandatory key"));
+}
+
+#[test]
+fn quiet_levels() {
+    let fixture = Fixture::new("log-quiet");
+    fixture.machine_config();
+
+    let output = fixture.machinegen(&["-q", "validate"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    fixture.write(".machinegen/config/user.json", "{}");
+    let output = fixture.machinegen(&["-qq", "validate"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Application error"));
+    let output = fixture.machinegen(&["-qqq", "validate"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn log_file_captures_subprocesses() {
+    let fixture = Fixture::new("log-file");
+    fixture.fake_terraform();
+    let terraform = fixture.read("bin/terraform");
+    fixture.write(
+        "bin/terraform",
+        terraform.replace("init) ", "init) echo 'Terraform has been initialized!' && "),
+    );
+    fixture.machine_config();
+    let image = fixture.write("upstream/base.qcow2", "image");
+    fixture.succeed(&["pull", "deps", "--image-url", &file_url(&image)]);
+
+    let log = fixture.path("machinegen.log");
+    let stdout = fixture.succeed(&[
+        "--log-file",
+        &log.display().to_string(),
+        "build",
+        "--all",
+        "--image",
+        "base",
+    ]);
+    assert!(
+        !stdout.contains("Terraform has been initialized!"),
+        "{}",
+        stdout
+    );
+    let log = fixture.read("machinegen.log");
+    assert!(
+        log.lines()
+            .any(|line| line.contains("debug") && line.ends_with("Terraform has been initialized!")),
+        "{}",
+        log
+    );
+    assert!(log.contains("Running terraform"), "{}", log);
+}
@@ -0,0 +1,210 @@
+//! `pull config`: the machine config out of a local bare repository, the user config out of
+//! local files and a local HTTP server.
+
+mod common;
+
+use common::{Fixture, Server};
+use std::path::Path;
+use std::process::Command;
+
+fn git(repository: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repository)
+        .args(args)
+        .env("GIT_AUTHOR_NAME", "machinegen")
+        .env("GIT_AUTHOR_EMAIL", "machinegen@localhost")
+        .env("GIT_COMMITTER_NAME", "machinegen")
+        .env("GIT_COMMITTER_EMAIL", "machinegen@localhost")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// Commits `content` at `file` in the upstream working copy and pushes it, returning the commit.
+fn publish(fixture: &Fixture, file: &str, content: &str) -> String {
+    let upstream = fixture.path("upstream");
+    fixture.write(&format!("upstream/{}", file), content);
+    git(&upstream, &["add", "-A"]);
+    git(&upstream, &["commit", "--quiet", "-m", file]);
+    git(&upstream, &["push", "--quiet", "origin", "HEAD:main"]);
+    git(&upstream, &["rev-parse", "HEAD"])
+}
+
+/// A bare `remote.git` with a `main` default branch, and the `upstream` working copy pushing to it.
+fn remote(fixture: &Fixture) -> String {
+    let remote = fixture.path("remote.git");
+    let upstream = fixture.path("upstream");
+    std::fs::create_dir_all(&remote).unwrap();
+    std::fs::create_dir_all(&upstream).unwrap();
+    git(&remote, &["init", "--quiet", "--bare"]);
+    git(&remote, &["symbolic-ref", "HEAD", "refs/heads/main"]);
+    git(&upstream, &["init", "--quiet"]);
+    git(&upstream, &["checkout", "--quiet", "-b", "main"]);
+    git(
+        &upstream,
+        &["remote", "add", "origin", &remote.display().to_string()],
+    );
+    remote.display().to_string()
+}
+
+fn lock(fixture: &Fixture) -> String {
+    fixture.read(".machinegen/config.lock")
+}
+
+#[test]
+fn clones_the_default_branch() {
+    let fixture = Fixture::new("config-clone");
+    let url = remote(&fixture);
+    let commit = publish(&fixture, "tables/replace.csv", "first");
+
+    fixture.succeed(&["pull", "config", "--machine", &url]);
+    assert_eq!(
+        fixture.read(".machinegen/config/tables/replace.csv"),
+        "first"
+    );
+    let lock = lock(&fixture);
+    assert!(lock.contains(&commit), "{}", lock);
+    assert!(lock.contains(&url), "{}", lock);
+}
+
+#[test]
+fn fast_forwards_the_branch() {
+    let fixture = Fixture::new("config-update");
+    let url = remote(&fixture);
+    publish(&fixture, "tables/replace.csv", "first");
+    fixture.succeed(&["pull", "config", "--machine", &url]);
+
+    let commit = publish(&fixture, "tables/replace.csv", "second");
+    fixture.succeed(&["pull", "config", "--machine", &url]);
+    assert_eq!(
+        fixture.read(".machinegen/config/tables/replace.csv"),
+        "second"
+    );
+    assert!(lock(&fixture).contains(&commit));
+}
+
+#[test]
+fn checks_out_a_tag() {
+    let fixture = Fixture::new("config-tag");
+    let url = remote(&fixture);
+    let tagged = publish(&fixture, "tables/replace.csv", "first");
+    git(&fixture.path("upstream"), &["tag", "v1"]);
+    git(
+        &fixture.path("upstream"),
+        &["push", "--quiet", "origin", "v1"],
+    );
+    publish(&fixture, "tables/replace.csv", "second");
+
+    fixture.succeed(&["pull", "config", "--machine", &url, "--ref", "v1"]);
+    assert_eq!(
+        fixture.read(".machinegen/config/tables/replace.csv"),
+        "first"
+    );
+    let lock = lock(&fixture);
+    assert!(lock.contains(&tagged), "{}", lock);
+    assert!(lock.contains("v1"), "{}", lock);
+
+    let stderr = fixture.fail(&["pull", "config", "--machine", &url, "--ref", "v2"], 7);
+    assert!(
+        stderr.contains("v2 is not a branch, tag or commit"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn local_changes_need_force() {
+    let fixture = Fixture::new("config-force");
+    let url = remote(&fixture);
+    publish(&fixture, "tables/replace.csv", "first");
+    fixture.succeed(&["pull", "config", "--machine", &url]);
+
+    // A local commit the remote doesn't have can't be fast-forwarded
+    let config = fixture.workspace("config");
+    fixture.write(".machinegen/config/tables/replace.csv", "local");
+    git(&config, &["commit", "--quiet", "-am", "local"]);
+    let commit = publish(&fixture, "tables/replace.csv", "second");
+    fixture.fail(&["pull", "config", "--machine", &url], 7);
+
+    // The user config lives in the same folder and survives the cleaning
+    fixture.write(".machinegen/config/user.json", "{}");
+    fixture.write(".machinegen/config/stray.txt", "stray");
+    fixture.succeed(&["pull", "config", "--machine", &url, "--force"]);
+    assert_eq!(
+        fixture.read(".machinegen/config/tables/replace.csv"),
+        "second"
+    );
+    assert!(lock(&fixture).contains(&commit));
+    assert_eq!(fixture.read(".machinegen/config/user.json"), "{}");
+    assert!(!fixture.workspace("config/stray.txt").exists());
+}
+
+#[test]
+fn user_config_over_http() {
+    let fixture = Fixture::new("user-http");
+    let server = Server::start(
+        vec![(
+            "/user.jsonc",
+            b"{\n  // The name\n  \"hostname\": \"vm\"\n}\n".to_vec(),
+        )],
+        false,
+    );
+
+    fixture.succeed(&[
+        "pull",
+        "config",
+        "--user",
+        &format!("{}/user.jsonc", server.url),
+    ]);
+    assert!(fixture
+        .read(".machinegen/config/user.jsonc")
+        .contains("\"hostname\": \"vm\""));
+    assert!(!fixture.workspace("config/user.part").exists());
+}
+
+#[test]
+fn user_config_from_a_path() {
+    let fixture = Fixture::new("user-path");
+    // No extension, the content tells the format
+    let source = fixture.write("settings", "{hostname: 'vm',}");
+    fixture.write(".machinegen/config/user.json", "{}");
+
+    let stdout = fixture.succeed(&["pull", "config", "--user", &source.display().to_string()]);
+    assert!(stdout.contains("already present"), "{}", stdout);
+    assert_eq!(fixture.read(".machinegen/config/user.json"), "{}");
+
+    fixture.succeed(&[
+        "pull",
+        "config",
+        "--user",
+        &source.display().to_string(),
+        "--force",
+    ]);
+    assert_eq!(
+        fixture.read(".machinegen/config/user.json5"),
+        "{hostname: 'vm',}"
+    );
+    // Only one user config at a time
+    assert!(!fixture.workspace("config/user.json").exists());
+}
+
+#[test]
+fn invalid_user_config_is_not_stored() {
+    let fixture = Fixture::new("user-invalid");
+    let source = fixture.write("user.json", "{\"hostname\": }");
+
+    let stderr = fixture.fail(
+        &["pull", "config", "--user", &source.display().to_string()],
+        4,
+    );
+    assert!(stderr.contains(":1:"), "{}", stderr);
+    assert!(!fixture.workspace("config/user.json").exists());
+    assert!(!fixture.workspace("config/user.part").exists());
+}
@@ -0,0 +1,204 @@
+//! `pull deps`: runtime dependencies and images out of `file://` URLs and a local HTTP server.
+
+mod common;
+
+use common::{file_url, sha256, Fixture, Server};
+use std::fs;
+
+const DEPENDENCIES_HEADER: &str = "name,version,url,sha256,target,executable\n";
+const IMAGES_HEADER: &str = "name,distro,release,arch,url,sha256,format\n";
+
+fn dependencies(fixture: &Fixture, rows: &[String]) {
+    fixture.write(
+        ".machinegen/config/tables/dependencies.csv",
+        format!("{}{}\n", DEPENDENCIES_HEADER, rows.join("\n")),
+    );
+}
+
+#[test]
+fn runtime_from_file_urls() {
+    let fixture = Fixture::new("runtime-file");
+    let tool = fixture.write("upstream/tool", "#!/bin/sh\necho tool\n");
+    let data = fixture.write("upstream/data.txt", "data");
+    dependencies(
+        &fixture,
+        &[
+            format!(
+                "tool,1.0,{},{},bin/tool,true",
+                file_url(&tool),
+                sha256(b"#!/bin/sh\necho tool\n")
+            ),
+            format!(
+                "data,2.0,{},{},share/data.txt,false",
+                file_url(&data),
+                sha256(b"data")
+            ),
+        ],
+    );
+
+    fixture.succeed(&["pull", "deps", "--runtime"]);
+    assert_eq!(
+        fixture.read(".machinegen/deps/bin/tool"),
+        "#!/bin/sh\necho tool\n"
+    );
+    assert_eq!(fixture.read(".machinegen/deps/share/data.txt"), "data");
+    assert!(fixture
+        .read(".machinegen/deps/share/data.txt.sha256")
+        .starts_with(&sha256(b"data")));
+    assert!(!fixture.workspace("deps/share/data.txt.part").exists());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path| {
+            fs::metadata(fixture.workspace(path))
+                .unwrap()
+                .permissions()
+                .mode()
+        };
+        assert_eq!(mode("deps/bin/tool") & 0o111, 0o111);
+        assert_eq!(mode("deps/share/data.txt") & 0o111, 0);
+    }
+
+    // Verified copies are kept as they are
+    let stdout = fixture.succeed(&["pull", "deps", "--runtime"]);
+    assert!(
+        stdout.contains("already present and verified"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn runtime_checksum_mismatch() {
+    let fixture = Fixture::new("runtime-mismatch");
+    let tool = fixture.write("upstream/tool", "tampered");
+    dependencies(
+        &fixture,
+        &[format!(
+            "tool,1.0,{},{},bin/tool,true",
+            file_url(&tool),
+            sha256(b"original")
+        )],
+    );
+
+    let stderr = fixture.fail(&["pull", "deps", "--runtime"], 8);
+    assert!(stderr.contains("checksum mismatch"), "{}", stderr);
+    assert!(!fixture.workspace("deps/bin/tool").exists());
+    assert!(!fixture.workspace("deps/bin/tool.part").exists());
+}
+
+#[test]
+fn runtime_targets_stay_in_deps() {
+    let fixture = Fixture::new("runtime-escape");
+    let tool = fixture.write("upstream/tool", "tool");
+    dependencies(
+        &fixture,
+        &[format!(
+            "tool,1.0,{},{},../escaped,false",
+            file_url(&tool),
+            sha256(b"tool")
+        )],
+    );
+
+    let stderr = fixture.fail(&["pull", "deps", "--runtime"], 8);
+    assert!(stderr.contains("inside the deps folder"), "{}", stderr);
+    assert!(!fixture.workspace("escaped").exists());
+}
+
+#[test]
+fn runtime_resumes_over_http() {
+    let fixture = Fixture::new("runtime-http");
+    let content: Vec<u8> = (0..200_000).map(|index| (index % 251) as u8).collect();
+    let server = Server::start(vec![("/tool", content.clone())], true);
+    dependencies(
+        &fixture,
+        &[format!(
+            "tool,1.0,{}/tool,{},bin/tool,true",
+            server.url,
+            sha256(&content)
+        )],
+    );
+
+    fixture.succeed(&["pull", "deps", "--runtime"]);
+    assert_eq!(
+        fs::read(fixture.workspace("deps/bin/tool")).unwrap(),
+        content
+    );
+
+    // The second attempt only asks for what the first one didn't get
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2, "{:?}", requests);
+    assert_eq!(requests[0].1, None);
+    assert_eq!(
+        requests[1].1.as_deref(),
+        Some(format!("bytes={}-", content.len() / 2).as_str())
+    );
+}
+
+#[test]
+fn runtime_http_not_found() {
+    let fixture = Fixture::new("runtime-404");
+    let server = Server::start(Vec::new(), false);
+    dependencies(
+        &fixture,
+        &[format!(
+            "tool,1.0,{}/missing,{},bin/tool,true",
+            server.url,
+            sha256(b"")
+        )],
+    );
+
+    let stderr = fixture.fail(&["pull", "deps", "--runtime"], 8);
+    assert!(stderr.contains("404"), "{}", stderr);
+    // Client errors are not retried
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn image_from_catalog_over_http() {
+    let fixture = Fixture::new("image-http");
+    let server = Server::start(vec![("/jammy.qcow2", b"qcow".to_vec())], false);
+    fixture.write(
+        ".machinegen/config/tables/images.csv",
+        format!(
+            "{}jammy,ubuntu,22.04,amd64,{}/jammy.qcow2,{},qcow2\n",
+            IMAGES_HEADER,
+            server.url,
+            sha256(b"qcow")
+        ),
+    );
+
+    fixture.succeed(&["pull", "deps", "--image"]);
+    assert_eq!(fixture.read(".machinegen/deps/images/jammy.qcow2"), "qcow");
+}
+
+#[test]
+fn image_from_url() {
+    let fixture = Fixture::new("image-url");
+    let image = fixture.write("upstream/base.img", "image");
+
+    fixture.succeed(&["pull", "deps", "--image-url", &file_url(&image)]);
+    assert_eq!(fixture.read(".machinegen/deps/images/base.img"), "image");
+    assert!(fixture
+        .read(".machinegen/deps/images/base.img.sha256")
+        .starts_with(&sha256(b"image")));
+}
+
+#[test]
+fn image_names_stay_in_images() {
+    let fixture = Fixture::new("image-escape");
+    let image = fixture.write("upstream/base.qcow2", "image");
+    fixture.write(
+        ".machinegen/config/tables/images.csv",
+        format!(
+            "{}../../escaped,ubuntu,22.04,amd64,{},{},qcow2\n",
+            IMAGES_HEADER,
+            file_url(&image),
+            sha256(b"image")
+        ),
+    );
+
+    let stderr = fixture.fail(&["pull", "deps", "--image", "../../escaped"], 4);
+    assert!(stderr.contains("plain file name"), "{}", stderr);
+    assert!(!fixture.path("escaped.qcow2").exists());
+}
@@ -0,0 +1,47 @@
+//! `validate`: the stored user config against the machine config tables.
+
+mod common;
+
+use common::Fixture;
+
+#[test]
+fn valid_user_config() {
+    let fixture = Fixture::new("validate-valid");
+    fixture.machine_config();
+
+    let stdout = fixture.succeed(&["validate"]);
+    assert!(stdout.contains("User config is valid."), "{}", stdout);
+}
+
+#[test]
+fn every_problem_is_reported() {
+    let fixture = Fixture::new("validate-invalid");
+    fixture.machine_config();
+    fixture.write(
+        ".machinegen/config/user.json",
+        r#"{"memory": "lots", "extra": 1}"#,
+    );
+
+    let stderr = fixture.fail(&["validate"], 5);
+    assert!(stderr.contains("3 problems"), "{}", stderr);
+    for problem in [
+        "extra: unknown key",
+        "hostname: missing mandatory key",
+        "memory: expected a size",
+    ] {
+        assert!(stderr.contains(problem), "{}", stderr);
+    }
+}
+
+#[test]
+fn invalid_user_config_builds_nothing() {
+    let fixture = Fixture::new("validate-build");
+    fixture.machine_config();
+    fixture.write(
+        ".machinegen/config/user.json",
+        r#"{"hostname": "vm", "memory": "lots"}"#,
+    );
+
+    fixture.fail(&["build", "--all"], 5);
+    assert!(!fixture.workspace("build").exists());
+}
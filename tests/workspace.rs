@@ -0,0 +1,129 @@
+//! Finding the workspace: `--workspace`, then `MACHINEGEN_HOME`, then the closest folder
+//! holding `.machinegen` going up from the current folder.
+
+mod common;
+
+use common::Fixture;
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Runs `machinegen -v list` in `current`, without `--workspace` unless given in `args`.
+fn list(current: &Path, home: Option<&Path>, args: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_machinegen"));
+    command
+        .args(args)
+        .args(["-v", "list"])
+        .current_dir(current)
+        .env("NO_COLOR", "1")
+        .env_remove("MACHINEGEN_HOME");
+    if let Some(home) = home {
+        command.env("MACHINEGEN_HOME", home);
+    }
+    command.output().unwrap()
+}
+
+fn workspace_of(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout
+        .lines()
+        .find_map(|line| line.split_once("of the workspace in "))
+        .map(|(_, workspace)| workspace.to_string())
+        .unwrap_or_else(|| panic!("no workspace in {}", stdout))
+}
+
+#[test]
+fn found_going_up() {
+    let fixture = Fixture::new("workspace-up");
+    fixture.machine_config();
+    let nested = fixture
+        .write("src/module/file", "")
+        .parent()
+        .unwrap()
+        .to_path_buf();
+
+    let output = list(&nested, None, &[]);
+    assert_eq!(workspace_of(&output), fixture.dir.display().to_string());
+}
+
+#[test]
+fn explicit_workspaces_come_first() {
+    let fixture = Fixture::new("workspace-explicit");
+    fixture.write("home/.machinegen/config/user.json", "{}");
+    fixture.write("given/.machinegen/config/user.json", "{}");
+    fixture.write("current/.machinegen/config/user.json", "{}");
+    let current = fixture.path("current");
+
+    let output = list(&current, Some(&fixture.path("home")), &[]);
+    assert_eq!(
+        workspace_of(&output),
+        fixture.path("home").display().to_string()
+    );
+
+    let output = list(
+        &current,
+        Some(&fixture.path("home")),
+        &["--workspace", "../given"],
+    );
+    assert_eq!(
+        workspace_of(&output),
+        fixture.path("current/../given").display().to_string()
+    );
+
+    let output = list(&current, Some(&fixture.path("missing")), &[]);
+    assert_eq!(output.status.code(), Some(9));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("given by MACHINEGEN_HOME"));
+}
+
+#[test]
+fn named_machines() {
+    let fixture = Fixture::new("workspace-machines");
+    fixture.machine_config();
+    let user = fixture.write("web.json", r#"{"hostname": "web", "memory": "2G"}"#);
+
+    fixture.succeed(&[
+        "--machine-name",
+        "web",
+        "pull",
+        "config",
+        "--user",
+        &user.display().to_string(),
+    ]);
+    assert!(fixture.workspace("machines/web/user.json").is_file());
+    // The default machine keeps its own user config
+    assert!(fixture
+        .read(".machinegen/config/user.json")
+        .contains("\"vm\""));
+
+    fixture.succeed(&["--machine-name", "web", "build", "--cloud"]);
+    assert_eq!(
+        fixture.read(".machinegen/machines/web/build/cloud-init/user-data"),
+        "#cloud-config\nhostname: web\n"
+    );
+    assert!(!fixture.workspace("build").exists());
+
+    let stdout = fixture.succeed(&["list"]);
+    let rows: Vec<Vec<&str>> = stdout
+        .lines()
+        .skip(1)
+        .map(|line| {
+            line.split("  ")
+                .map(str::trim)
+                .filter(|cell| !cell.is_empty())
+                .collect()
+        })
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            vec!["default", "user.json", "not built", "never deployed"],
+            vec!["web", "user.json", "seed image built", "never deployed"],
+        ]
+    );
+
+    fixture.fail(&["--machine-name", "../web", "list"], 4);
+}